@@ -5,6 +5,8 @@ use anyhow::Result;
 mod registry;
 mod elevation;
 mod install;
+#[path = "../menu.rs"]
+mod menu;
 
 use install::{install_organizer, uninstall_organizer};
 
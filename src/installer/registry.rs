@@ -90,14 +90,9 @@ fn create_menu_entries(shell_key: &RegKey, organizer_path: &Path) -> Result<()>
     let (submenu_shell, _) = parent_key.create_subkey_with_flags("shell", KEY_READ | KEY_WRITE)
         .map_err(|e| anyhow::anyhow!("Failed to create shell under OrganizeMenu: {}", e))?;
 
-    create_single_entry(&submenu_shell, "OrganizeByType", "Organize by File Type", &organizer_path_str, "type")?;
-    create_single_entry(&submenu_shell, "OrganizeByDate", "Organize by Date Created", &organizer_path_str, "date")?;
-    create_single_entry(&submenu_shell, "OrganizeByModifiedDate", "Organize by Date Modified", &organizer_path_str, "modified_date")?;
-    create_single_entry(&submenu_shell, "OrganizeBySize", "Organize by File Size", &organizer_path_str, "size")?;
-    create_single_entry(&submenu_shell, "OrganizeByName", "Organize by Name (Alphabetical)", &organizer_path_str, "name")?;
-    create_single_entry(&submenu_shell, "FlattenFolder", "Flatten Folder Structure", &organizer_path_str, "flatten")?;
-    create_single_entry(&submenu_shell, "RemoveDuplicates", "Remove Duplicate Files", &organizer_path_str, "remove_duplicates")?;
-    create_single_entry(&submenu_shell, "UndoOrganize", "Undo Last Organization", &organizer_path_str, "undo")?;
+    for entry in crate::menu::entries() {
+        create_single_entry(&submenu_shell, entry.key, entry.label, &organizer_path_str, entry.mode)?;
+    }
 
     println!("✅ Created Organize submenu with child entries");
     Ok(())
@@ -1,16 +1,137 @@
 #![windows_subsystem = "windows"]
-use clap::{Arg, Command};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{Arg, ArgAction, Command};
 use std::path::PathBuf;
 use anyhow::Result;
 
+mod menu;
 mod organizer;
 mod undo;
 
-use organizer::{by_type, by_date, by_name, by_modified_date, by_size, flatten, remove_duplicates};
+use organizer::journal::{self, Recovery};
+use organizer::quarantine;
+use organizer::{ModeOptions, execute, find_organizer, registry};
+use undo::UndoTarget;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let matches = Command::new("organizer")
+    attach_console();
+    let matches = build_cli().get_matches();
+
+    let mode = matches.get_one::<String>("mode").unwrap();
+    let path = PathBuf::from(matches.get_one::<String>("path").unwrap());
+
+    if !path.exists() || !path.is_dir() {
+        eprintln!("Error: Path does not exist or is not a directory: {}", path.display());
+        std::process::exit(1);
+    }
+
+    let dry_run = matches.get_one::<String>("dry-run").map(String::as_str);
+
+    // A dry run or report must not touch the disk, recovery included
+    if dry_run.is_some() || matches.get_one::<String>("report").is_some() {
+        if journal::get_journal_path(&path).exists() {
+            println!("Note: an interrupted operation is pending in this folder.");
+        }
+    } else {
+        let recovery = matches.get_one::<String>("recover").map(|r| match r.as_str() {
+            "finish" => Recovery::Finish,
+            _ => Recovery::Rollback,
+        });
+        if !journal::recover(&path, recovery).await? {
+            println!("Interrupted operation left as is.");
+            return Ok(());
+        }
+    }
+
+    match mode.as_str() {
+        "undo" => {
+            let target = match (matches.get_one::<u64>("id"), matches.get_one::<usize>("steps")) {
+                (Some(id), _) => UndoTarget::Id(*id),
+                (None, steps) => UndoTarget::Steps(steps.copied().unwrap_or(1)),
+            };
+            return undo::undo(&path, target, dry_run.is_some()).await;
+        }
+        "redo" => return undo::redo(&path, dry_run.is_some()).await,
+        "history" => return undo::list_history(&path).await,
+        "purge_quarantine" => {
+            let global = matches.get_one::<String>("quarantine").map(|dir| path.join(dir));
+            let older_than = quarantine::parse_age(matches.get_one::<String>("older-than").unwrap())?;
+            let root = quarantine::quarantine_root(&path, global.as_deref());
+            return quarantine::purge(&root, older_than, dry_run.is_some()).await;
+        }
+        _ => {}
+    }
+
+    let organizer = find_organizer(mode).unwrap();
+    let mut options = ModeOptions::default();
+    for option in organizer.options() {
+        if option.flag {
+            options.set(option.name, matches.get_flag(option.name).to_string());
+        } else if let Some(value) = matches.get_one::<String>(option.name) {
+            options.set(option.name, value.clone());
+        }
+    }
+    for name in ["dry-run", "layout", "rename", "timezone", "max-depth", "hidden", "quarantine"] {
+        if let Some(value) = matches.get_one::<String>(name) {
+            options.set(name, value.clone());
+        }
+    }
+    for name in ["recursive", "collect"] {
+        options.set(name, matches.get_flag(name).to_string());
+    }
+    for name in ["include", "exclude"] {
+        if let Some(patterns) = matches.get_many::<String>(name) {
+            options.set(name, patterns.cloned().collect::<Vec<_>>().join("\n"));
+        }
+    }
+
+    if let Some(format) = options.get::<String>("report") {
+        println!("{}", organizer.report(&path, &format, &options).await?);
+        return Ok(());
+    }
+
+    let plan = organizer.plan(&path, &options).await?;
+
+    match dry_run {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&plan)?),
+        Some(_) => println!("{}", plan),
+        None if plan.is_empty() => println!("Nothing to do: {}", organizer.description()),
+        None => {
+            let action = execute(&path, plan).await?;
+            println!("{}: {}", organizer.description(), action.plan.summary());
+        }
+    }
+
+    Ok(())
+}
+
+/// Without a console of its own the program writes nowhere, so previews,
+/// reports and the history are shown in the console it was started from.
+/// Started from Explorer there is none and nothing changes.
+#[cfg(windows)]
+fn attach_console() {
+    use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+fn build_cli() -> Command {
+    let modes = registry().iter()
+        .map(|o| PossibleValue::new(o.name()).help(o.description()))
+        .chain([
+            PossibleValue::new("undo").help("Undo Last Organization"),
+            PossibleValue::new("redo").help("Redo the last undone organization"),
+            PossibleValue::new("history").help("List the recorded organizations"),
+            PossibleValue::new("purge_quarantine").help("Permanently delete quarantined duplicates"),
+        ]);
+
+    let mut command = Command::new("organizer")
         .version("1.0")
         .about("File organization tool for Windows Explorer context menu")
         .arg(
@@ -18,7 +139,8 @@ async fn main() -> Result<()> {
                 .short('m')
                 .long("mode")
                 .value_name("MODE")
-                .help("Organization mode: type, date, modified_date, name, size, flatten, remove_duplicates, or undo")
+                .help("Organization mode")
+                .value_parser(PossibleValuesParser::new(modes))
                 .required(true)
         )
         .arg(
@@ -28,38 +150,118 @@ async fn main() -> Result<()> {
                 .index(1)
         )
         .arg(
-            Arg::new("ranges")
-                .short('r')
-                .long("ranges")
+            Arg::new("dry-run")
+                .long("dry-run")
+                .value_name("FORMAT")
+                .help("Print what would be done without touching any file")
+                .value_parser(["text", "json"])
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("text")
+        )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .value_name("TEMPLATE")
+                .help("Destination folders as a template, e.g. \"{year}/{month:02}\" or \"{type}/{ext}\"")
+        )
+        .arg(
+            Arg::new("rename")
+                .long("rename")
+                .value_name("TEMPLATE")
+                .help("Rename files while organizing, e.g. \"{created:%Y%m%d_%H%M%S}_{stem}{ext}\"")
+        )
+        .arg(
+            Arg::new("recursive")
+                .long("recursive")
+                .action(ArgAction::SetTrue)
+                .help("Organize every subfolder too, each in place")
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .help("Organize subfolders down to N levels below the folder, implies --recursive")
+        )
+        .arg(
+            Arg::new("collect")
+                .long("collect")
+                .action(ArgAction::SetTrue)
+                .help("With --recursive, gather the files of all subfolders into the top-level folders")
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .help("Only organize files matching this glob, e.g. \"*.pdf\" (repeatable)")
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .help("Leave files and folders matching this glob alone, e.g. \"*.lnk\" or Projects (repeatable)")
+        )
+        .arg(
+            Arg::new("hidden")
+                .long("hidden")
+                .value_name("POLICY")
+                .help("Hidden files to leave alone: hidden (dot files and files marked hidden or system, the default), dotfiles, system or none")
+                .value_parser(["hidden", "dotfiles", "system", "none"])
+        )
+        .arg(
+            Arg::new("recover")
+                .long("recover")
+                .value_name("ACTION")
+                .help("What to do with an interrupted operation instead of asking")
+                .value_parser(["finish", "rollback"])
+        )
+        .arg(
+            Arg::new("id")
+                .long("id")
+                .value_name("ID")
+                .help("Undo the action with this id and everything after it")
+                .value_parser(clap::value_parser!(u64))
+                .conflicts_with("steps")
+        )
+        .arg(
+            Arg::new("steps")
+                .long("steps")
                 .value_name("NUMBER")
-                .help("Number of alphabetical ranges for name organization (default: 4)")
-                .default_value("4")
+                .help("Number of actions to undo (default: 1)")
+                .value_parser(clap::value_parser!(usize))
         )
-        .get_matches();
-
-    let mode = matches.get_one::<String>("mode").unwrap();
-    let path = PathBuf::from(matches.get_one::<String>("path").unwrap());
-    let ranges: usize = matches.get_one::<String>("ranges").unwrap().parse().unwrap_or(4);
+        .arg(
+            Arg::new("older-than")
+                .long("older-than")
+                .value_name("AGE")
+                .help("Only purge quarantined files older than this, e.g. 30d or 12h")
+                .default_value("30d")
+        );
 
-    if !path.exists() || !path.is_dir() {
-        eprintln!("Error: Path does not exist or is not a directory: {}", path.display());
-        std::process::exit(1);
-    }
+    // Options are shared by name, so two modes may declare the same one
+    for option in registry().iter().flat_map(|o| o.options()) {
+        if command.get_arguments().any(|a| a.get_id() == option.name) {
+            continue;
+        }
 
-    match mode.as_str() {
-        "type" => by_type::organize_by_type(&path).await?,
-        "date" => by_date::organize_by_date(&path).await?,
-        "modified_date" => by_modified_date::organize_by_modified_date(&path).await?,
-        "name" => by_name::organize_by_name(&path, ranges).await?,
-        "size" => by_size::organize_by_size(&path).await?,
-        "flatten" => flatten::flatten_folder(&path).await?,
-        "remove_duplicates" => remove_duplicates::remove_duplicates(&path).await?,
-        "undo" => undo::undo_last_action(&path).await?,
-        _ => {
-            eprintln!("Error: Invalid mode. Use: type, date, name, size, flatten, remove_duplicates, or undo");
-            std::process::exit(1);
+        let mut arg = Arg::new(option.name)
+            .long(option.name)
+            .help(option.help);
+        arg = if option.flag {
+            arg.action(ArgAction::SetTrue)
+        } else {
+            arg.value_name(option.value_name)
+        };
+        if let Some(short) = option.short {
+            arg = arg.short(short);
         }
+        if let Some(default) = option.default {
+            arg = arg.default_value(default);
+        }
+        command = command.arg(arg);
     }
 
-    Ok(())
-}
\ No newline at end of file
+    command
+}
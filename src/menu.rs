@@ -0,0 +1,25 @@
+/// One entry of the Organize context menu
+pub struct MenuEntry {
+    /// Value passed to `--mode`
+    pub mode: &'static str,
+    /// Registry key name of the entry
+    #[allow(dead_code)] // only read by the installer
+    pub key: &'static str,
+    pub label: &'static str,
+}
+
+/// The entries of `menu.txt`, in menu order
+pub fn entries() -> impl Iterator<Item = MenuEntry> {
+    include_str!("menu.txt").lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '|');
+            Some(MenuEntry { mode: fields.next()?, key: fields.next()?, label: fields.next()? })
+        })
+}
+
+/// The menu label of `mode`
+pub fn label(mode: &str) -> Option<&'static str> {
+    entries().find(|entry| entry.mode == mode).map(|entry| entry.label)
+}
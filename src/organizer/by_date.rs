@@ -1,81 +1,224 @@
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
 use anyhow::Result;
-use tokio::fs;
-use chrono::{DateTime, Utc};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Utc};
+use chrono_tz::Tz;
 
-use super::{OrganizeAction, FileMove, log_action};
+use super::exif_data::read_exif;
+use super::photo_date::date_from_file_name;
+use super::{Entry, ModeOption, ModeOptions, Organizer, Plan, plan_entries};
 
-pub async fn organize_by_date(folder: &Path) -> Result<()> {
-    let mut entries = fs::read_dir(folder).await?;
-    let mut moves = Vec::new();
+static OPTIONS: &[ModeOption] = &[
+    ModeOption {
+        name: "source",
+        short: None,
+        value_name: "SOURCE",
+        help: "Date to sort by: created, modified, accessed, exif or filename (default: created for date, modified for modified_date)",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "granularity",
+        short: None,
+        value_name: "UNIT",
+        help: "Date folders to create: year, quarter, month, week or day",
+        default: Some("month"),
+        flag: false,
+    },
+    ModeOption {
+        name: "timezone",
+        short: None,
+        value_name: "ZONE",
+        help: "Time zone of the date folders and of dates in --layout and --rename: local, utc or an IANA name like Europe/Berlin",
+        default: Some("local"),
+        flag: false,
+    },
+];
 
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
+/// Where the date of a file is taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateSource {
+    Created,
+    Modified,
+    Accessed,
+    /// EXIF capture date, files without one stay where they are
+    Exif,
+    /// A date in the file name like `IMG_20240315_101112.jpg`
+    FileName,
+}
+
+impl FromStr for DateSource {
+    type Err = anyhow::Error;
 
-        // Skip directories and hidden files
-        if path.is_dir() || path.file_name().unwrap().to_string_lossy().starts_with('.') {
-            continue;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "created" => Ok(DateSource::Created),
+            "modified" => Ok(DateSource::Modified),
+            "accessed" => Ok(DateSource::Accessed),
+            "exif" => Ok(DateSource::Exif),
+            "filename" => Ok(DateSource::FileName),
+            other => anyhow::bail!("Unknown date source '{}', use created, modified, accessed, exif or filename", other),
         }
+    }
+}
 
-        let metadata = fs::metadata(&path).await?;
-        let created = metadata.created()?;
-        let datetime: DateTime<Utc> = created.into();
+/// How finely files are split into date folders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Year,
+    Quarter,
+    Month,
+    /// ISO weeks, filed under the year the week belongs to
+    Week,
+    Day,
+}
 
-        // Create year folder
-        let year = datetime.format("%Y").to_string();
-        let year_folder = folder.join(&year);
-        if !year_folder.exists() {
-            fs::create_dir(&year_folder).await?;
+impl FromStr for Granularity {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "year" => Ok(Granularity::Year),
+            "quarter" => Ok(Granularity::Quarter),
+            "month" => Ok(Granularity::Month),
+            "week" => Ok(Granularity::Week),
+            "day" => Ok(Granularity::Day),
+            other => anyhow::bail!("Unknown granularity '{}', use year, quarter, month, week or day", other),
         }
+    }
+}
 
-        // Create month folder
-        let month = datetime.format("%Y-%m").to_string();
-        let month_folder = year_folder.join(&month);
-        if !month_folder.exists() {
-            fs::create_dir(&month_folder).await?;
+impl Granularity {
+    /// Date folders like `2024/2024-03`, relative to the organized folder
+    fn folders(self, date: NaiveDateTime) -> PathBuf {
+        let year = date.format("%Y").to_string();
+        match self {
+            Granularity::Year => PathBuf::from(year),
+            Granularity::Quarter => Path::new(&year).join(format!("{}-Q{}", year, (date.month() - 1) / 3 + 1)),
+            Granularity::Month => Path::new(&year).join(date.format("%Y-%m").to_string()),
+            Granularity::Week => Path::new(&date.format("%G").to_string()).join(date.format("%G-W%V").to_string()),
+            Granularity::Day => Path::new(&year)
+                .join(date.format("%Y-%m").to_string())
+                .join(date.format("%Y-%m-%d").to_string()),
         }
+    }
+}
+
+/// Time zone file times are converted to before picking a folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeZone {
+    Local,
+    Utc,
+    Named(Tz),
+}
 
-        // Move file
-        let new_path = month_folder.join(path.file_name().unwrap());
-        let new_path = handle_name_conflict(&new_path).await?;
+impl FromStr for TimeZone {
+    type Err = anyhow::Error;
 
-        fs::rename(&path, &new_path).await?;
-        moves.push(FileMove {
-            from: path,
-            to: new_path,
-        });
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(TimeZone::Local),
+            "utc" => Ok(TimeZone::Utc),
+            _ => value.parse::<Tz>().map(TimeZone::Named).map_err(|_| {
+                anyhow::anyhow!("Unknown time zone '{}', use local, utc or an IANA name like Europe/Berlin", value)
+            }),
+        }
     }
+}
 
-    // Log the action
-    let action = OrganizeAction {
-        timestamp: Utc::now(),
-        action_type: "by_date".to_string(),
-        moves,
-    };
-    log_action(folder, action.clone()).await?;
+impl TimeZone {
+    /// The `--timezone` of a run, local time if there is none
+    pub fn from_options(options: &ModeOptions) -> Result<Self> {
+        options.get::<String>("timezone").as_deref().unwrap_or("local").parse()
+    }
 
-    println!("Successfully organized {} files by date", action.moves.len());
-    Ok(())
+    /// The wall clock time `time` shows in this zone
+    pub fn wall_clock(self, time: SystemTime) -> NaiveDateTime {
+        match self {
+            TimeZone::Local => DateTime::<Local>::from(time).naive_local(),
+            TimeZone::Utc => DateTime::<Utc>::from(time).naive_utc(),
+            TimeZone::Named(tz) => DateTime::<Utc>::from(time).with_timezone(&tz).naive_local(),
+        }
+    }
 }
 
-async fn handle_name_conflict(path: &Path) -> Result<PathBuf> {
-    if !path.exists() {
-        return Ok(path.to_path_buf());
+/// The date options of a run
+struct DateSettings {
+    source: DateSource,
+    granularity: Granularity,
+    timezone: TimeZone,
+}
+
+/// Sorts files into date folders. `date` and `modified_date` only differ in
+/// the date they use when no `--source` is given.
+pub struct ByDate {
+    name: &'static str,
+    default_source: DateSource,
+}
+
+pub static BY_CREATED: ByDate = ByDate {
+    name: "date",
+    default_source: DateSource::Created,
+};
+
+pub static BY_MODIFIED: ByDate = ByDate {
+    name: "modified_date",
+    default_source: DateSource::Modified,
+};
+
+impl ByDate {
+    fn settings(&self, options: &ModeOptions) -> Result<DateSettings> {
+        Ok(DateSettings {
+            source: match options.get::<String>("source") {
+                Some(source) => source.parse()?,
+                None => self.default_source,
+            },
+            granularity: options.get::<String>("granularity").as_deref().unwrap_or("month").parse()?,
+            timezone: TimeZone::from_options(options)?,
+        })
     }
 
-    let parent = path.parent().unwrap();
-    let filename = path.file_stem().unwrap().to_string_lossy();
-    let extension = path.extension()
-        .map(|e| format!(".{}", e.to_string_lossy()))
-        .unwrap_or_default();
-
-    for i in 1..1000 {
-        let new_name = format!("{} ({}){}", filename, i, extension);
-        let new_path = parent.join(new_name);
-        if !new_path.exists() {
-            return Ok(new_path);
+    /// EXIF and file name dates are already wall clock times and are used as they are
+    fn date(&self, entry: &Entry, settings: &DateSettings) -> Option<NaiveDateTime> {
+        let metadata = &entry.metadata;
+        match settings.source {
+            DateSource::Created => Some(settings.timezone.wall_clock(metadata.created().ok()?)),
+            DateSource::Modified => Some(settings.timezone.wall_clock(metadata.modified().ok()?)),
+            DateSource::Accessed => Some(settings.timezone.wall_clock(metadata.accessed().ok()?)),
+            DateSource::Exif => read_exif(&entry.path)?.taken,
+            DateSource::FileName => date_from_file_name(&entry.path.file_stem()?.to_string_lossy()),
         }
     }
+}
+
+#[async_trait]
+impl Organizer for ByDate {
+    fn name(&self) -> &'static str {
+        self.name
+    }
 
-    Err(anyhow::anyhow!("Could not resolve name conflict for: {}", path.display()))
-}
\ No newline at end of file
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
+    }
+
+    /// The sorted-by date, so templates show the same day as the folders
+    fn layout_date(&self, entry: &Entry, options: &ModeOptions) -> Option<NaiveDateTime> {
+        let settings = self.settings(options).ok()?;
+        self.date(entry, &settings)
+    }
+
+    fn classify(&self, entry: &Entry, options: &ModeOptions) -> Option<PathBuf> {
+        let settings = self.settings(options).ok()?;
+        let date = self.date(entry, &settings)?;
+
+        Some(settings.granularity.folders(date).join(entry.path.file_name()?))
+    }
+
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        // Report bad option values instead of silently leaving every file in place
+        self.settings(options)?;
+        plan_entries(self, folder, options).await
+    }
+}
@@ -1,115 +1,271 @@
 use std::path::{Path, PathBuf};
 use anyhow::Result;
-use tokio::fs;
-use chrono::Utc;
+use async_trait::async_trait;
+use unicode_normalization::UnicodeNormalization;
 
-use super::{OrganizeAction, FileMove, log_action};
+use super::{Entry, ModeOption, ModeOptions, Organizer, Plan, Walk, collect_entries, plan_classified, plan_entries};
 
-pub async fn organize_by_name(folder: &Path, num_ranges: usize) -> Result<()> {
-    let mut entries = fs::read_dir(folder).await?;
-    let mut moves = Vec::new();
+static OPTIONS: &[ModeOption] = &[
+    ModeOption {
+        name: "ranges",
+        short: Some('r'),
+        value_name: "NUMBER|SPEC",
+        help: "Number of alphabetical ranges (1-26), or the ranges themselves like A-C,D-H,I-Z",
+        default: Some("4"),
+        flag: false,
+    },
+    ModeOption {
+        name: "balanced",
+        short: None,
+        value_name: "",
+        help: "Pick the --ranges letter ranges so each folder gets about the same number of files",
+        default: None,
+        flag: true,
+    },
+];
 
-    let ranges = create_alphabetical_ranges(num_ranges);
+/// Folder of names starting with a digit
+const DIGITS: &str = "0-9";
+/// Folder of names that start with neither a letter nor a digit
+const OTHER: &str = "Other";
 
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
+/// First letter ranges of Latin names, the other scripts get one folder each
+struct LetterRanges {
+    ranges: Vec<(char, char, String)>,
+}
 
-        // Skip directories and hidden files
-        if path.is_dir() || path.file_name().unwrap().to_string_lossy().starts_with('.') {
-            continue;
+impl LetterRanges {
+    fn from_options(options: &ModeOptions) -> Result<Self> {
+        let value = options.get::<String>("ranges").unwrap_or_else(|| "4".to_string());
+        match value.trim().parse::<usize>() {
+            Ok(count) => LetterRanges::even(count),
+            Err(_) => LetterRanges::parse(&value),
         }
+    }
 
-        if let Some(filename) = path.file_name() {
-            let first_char = filename.to_string_lossy()
-                .chars()
-                .next()
-                .unwrap_or('_')
-                .to_ascii_uppercase();
+    /// `count` ranges whose sizes differ by at most one letter
+    fn even(count: usize) -> Result<Self> {
+        if !(1..=26).contains(&count) {
+            anyhow::bail!("--ranges has to be between 1 and 26, or a list like A-C,D-H,I-Z");
+        }
 
-            let range_folder_name = find_range_for_char(first_char, &ranges);
-            let range_folder = folder.join(&range_folder_name);
+        let letter = |index: usize| (index as u8 + b'A') as char;
+        let ranges = (0..count)
+            .map(|i| (letter(i * 26 / count), letter((i + 1) * 26 / count - 1)))
+            .collect();
+        Ok(LetterRanges::named(ranges))
+    }
 
-            if !range_folder.exists() {
-                fs::create_dir(&range_folder).await?;
+    /// Ranges like `A-C,D-H,I-Z` or single letters like `Q`. Letters no range
+    /// covers go to "Other".
+    fn parse(spec: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid --ranges '{}', use a number or ranges like A-C,D-H,I-Z", spec);
+        let letter = |text: &str| -> Result<char> {
+            let mut chars = text.trim().chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => Ok(c.to_ascii_uppercase()),
+                _ => Err(invalid()),
             }
+        };
+
+        let mut ranges = spec.split(',')
+            .map(|range| match range.split_once('-') {
+                Some((start, end)) => Ok((letter(start)?, letter(end)?)),
+                None => letter(range).map(|c| (c, c)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        ranges.sort();
+        if ranges.iter().any(|(start, end)| start > end) || ranges.windows(2).any(|pair| pair[0].1 >= pair[1].0) {
+            anyhow::bail!("The ranges in --ranges '{}' must not be reversed or overlap", spec);
+        }
+        Ok(LetterRanges::named(ranges))
+    }
+
+    fn named(ranges: Vec<(char, char)>) -> Self {
+        let ranges = ranges.into_iter()
+            .map(|(start, end)| {
+                let folder_name = if start == end { start.to_string() } else { format!("{}-{}", start, end) };
+                (start, end, folder_name)
+            })
+            .collect();
+        LetterRanges { ranges }
+    }
 
-            // Move file
-            let new_path = range_folder.join(filename);
-            let new_path = handle_name_conflict(&new_path).await?;
+    fn folder(&self, name: &str) -> String {
+        let Some(first) = name.chars().find(|c| !c.is_whitespace()) else {
+            return OTHER.to_string();
+        };
 
-            fs::rename(&path, &new_path).await?;
-            moves.push(FileMove {
-                from: path,
-                to: new_path,
-            });
+        let ch = fold(first);
+        if ch.is_ascii_alphabetic() {
+            self.ranges.iter()
+                .find(|(start, end, _)| (*start..=*end).contains(&ch))
+                .map(|(_, _, folder_name)| folder_name.clone())
+                .unwrap_or_else(|| OTHER.to_string())
+        } else if ch.is_ascii_digit() && first.to_string().nfkd().count() == 1 {
+            // Fractions like ½ decompose into digits as well, but are none
+            DIGITS.to_string()
+        } else {
+            script(ch).unwrap_or(OTHER).to_string()
         }
     }
+}
 
-    // Log the action
-    let action = OrganizeAction {
-        timestamp: Utc::now(),
-        action_type: format!("by_name_{}", num_ranges),
-        moves,
+/// The base letter of `ch` in upper case, so `é` and `É` both become `E` and
+/// full-width `Ａ` becomes `A`
+pub fn fold(ch: char) -> char {
+    let base = ch.to_string().nfkd().next().unwrap_or(ch);
+    // Letters with a stroke or ligatures have no decomposition
+    let base = match base {
+        'Ø' | 'ø' | 'Œ' | 'œ' => 'O',
+        'Æ' | 'æ' => 'A',
+        'Ł' | 'ł' => 'L',
+        'Đ' | 'đ' | 'Ð' | 'ð' => 'D',
+        'Þ' | 'þ' => 'T',
+        'ß' => 'S',
+        'ı' => 'I',
+        other => other,
     };
-    log_action(folder, action.clone()).await?;
+    base.to_uppercase().next().unwrap_or(base)
+}
 
-    println!("Successfully organized {} files by name into {} ranges", action.moves.len(), num_ranges);
-    Ok(())
+/// Folder of a letter from a script other than Latin
+fn script(ch: char) -> Option<&'static str> {
+    let script = match ch as u32 {
+        0x0370..=0x03FF | 0x1F00..=0x1FFF => "Greek",
+        0x0400..=0x052F | 0x1C80..=0x1C8F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => "Cyrillic",
+        0x0530..=0x058F => "Armenian",
+        0x0590..=0x05FF => "Hebrew",
+        0x0600..=0x06FF | 0x0750..=0x077F | 0x08A0..=0x08FF => "Arabic",
+        0x0900..=0x097F => "Devanagari",
+        0x0E00..=0x0E7F => "Thai",
+        0x10A0..=0x10FF => "Georgian",
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => "Hangul",
+        0x3040..=0x30FF | 0x31F0..=0x31FF => "Kana",
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x2FA1F => "CJK",
+        _ => return None,
+    };
+    Some(script)
 }
 
-fn create_alphabetical_ranges(num_ranges: usize) -> Vec<(char, char, String)> {
-    let letters_per_range = 26 / num_ranges;
-    let mut ranges = Vec::new();
+/// Cuts A-Z into up to `count` ranges holding about the same number of the
+/// given first letters
+fn balanced_ranges(initials: &[char], count: usize) -> Vec<(char, char)> {
+    let mut per_letter = [0usize; 26];
+    for ch in initials {
+        per_letter[(*ch as u8 - b'A') as usize] += 1;
+    }
+    let total: usize = per_letter.iter().sum();
 
-    for i in 0..num_ranges {
-        let start = ((i * letters_per_range) as u8 + b'A') as char;
-        let end = if i == num_ranges - 1 {
-            'Z'  // Last range gets all remaining letters
-        } else {
-            (((i + 1) * letters_per_range - 1) as u8 + b'A') as char
-        };
+    // Each letter goes to the range its files' midpoint falls into
+    let mut ranges: Vec<(char, char, usize)> = Vec::new();
+    let mut before = 0;
+    for (index, &files) in per_letter.iter().enumerate() {
+        let letter = (index as u8 + b'A') as char;
+        let range = ((2 * before + files) * count / (2 * total.max(1))).min(count - 1);
+        before += files;
 
-        let folder_name = if start == end {
-            format!("{}", start)
-        } else {
-            format!("{}-{}", start, end)
-        };
+        match ranges.last_mut() {
+            Some(last) if last.2 == range => last.1 = letter,
+            _ => ranges.push((letter, letter, range)),
+        }
+    }
+    ranges.into_iter().map(|(start, end, _)| (start, end)).collect()
+}
+
+pub struct ByName;
 
-        ranges.push((start, end, folder_name));
+#[async_trait]
+impl Organizer for ByName {
+    fn name(&self) -> &'static str {
+        "name"
     }
 
-    ranges
-}
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
+    }
 
-fn find_range_for_char(ch: char, ranges: &[(char, char, String)]) -> String {
-    for (start, end, folder_name) in ranges {
-        if ch >= *start && ch <= *end {
-            return folder_name.clone();
+    fn action_type(&self, options: &ModeOptions) -> String {
+        match options.get::<usize>("ranges") {
+            Some(count) => format!("by_name_{}", count),
+            None => "by_name".to_string(),
         }
     }
 
-    // For non-alphabetic characters
-    "Other".to_string()
-}
+    fn classify(&self, entry: &Entry, options: &ModeOptions) -> Option<PathBuf> {
+        let ranges = LetterRanges::from_options(options).ok()?;
+        let filename = entry.path.file_name()?;
+
+        let range_folder_name = ranges.folder(&filename.to_string_lossy());
+        Some(Path::new(&range_folder_name).join(filename))
+    }
 
-async fn handle_name_conflict(path: &Path) -> Result<PathBuf> {
-    if !path.exists() {
-        return Ok(path.to_path_buf());
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        LetterRanges::from_options(options)?;
+        if !options.get::<bool>("balanced").unwrap_or(false) {
+            return plan_entries(self, folder, options).await;
+        }
+
+        let count = options.get::<usize>("ranges")
+            .ok_or_else(|| anyhow::anyhow!("--balanced needs --ranges to be a number of folders"))?;
+        let initials: Vec<char> = collect_entries(folder, &Walk::from_options(self, folder, options).await?).await?
+            .iter()
+            .filter_map(|entry| entry.path.file_name()?.to_string_lossy().chars().find(|c| !c.is_whitespace()))
+            .map(fold)
+            .filter(|ch| ch.is_ascii_alphabetic())
+            .collect();
+
+        let ranges = LetterRanges::named(balanced_ranges(&initials, count));
+        plan_classified(self, folder, options, |entry| {
+            let filename = entry.path.file_name()?;
+            Some(Path::new(&ranges.folder(&filename.to_string_lossy())).join(filename))
+        }).await
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let parent = path.parent().unwrap();
-    let filename = path.file_stem().unwrap().to_string_lossy();
-    let extension = path.extension()
-        .map(|e| format!(".{}", e.to_string_lossy()))
-        .unwrap_or_default();
+    fn folder_names(ranges: &LetterRanges) -> Vec<&str> {
+        ranges.ranges.iter().map(|(_, _, name)| name.as_str()).collect()
+    }
 
-    for i in 1..1000 {
-        let new_name = format!("{} ({}){}", filename, i, extension);
-        let new_path = parent.join(new_name);
-        if !new_path.exists() {
-            return Ok(new_path);
+    #[test]
+    fn even_ranges_spread_the_remainder() {
+        assert_eq!(folder_names(&LetterRanges::even(4).unwrap()), ["A-F", "G-M", "N-S", "T-Z"]);
+        assert_eq!(folder_names(&LetterRanges::even(26).unwrap()).len(), 26);
+        for count in 1..=26 {
+            let ranges = LetterRanges::even(count).unwrap();
+            let sizes: Vec<u32> = ranges.ranges.iter().map(|(start, end, _)| *end as u32 - *start as u32 + 1).collect();
+            assert_eq!(sizes.iter().sum::<u32>(), 26);
+            assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1, "{} ranges: {:?}", count, sizes);
         }
+        assert!(LetterRanges::even(0).is_err() && LetterRanges::even(27).is_err());
+    }
+
+    #[test]
+    fn explicit_ranges() {
+        let ranges = LetterRanges::parse("a-c, d-h,Q").unwrap();
+        assert_eq!(folder_names(&ranges), ["A-C", "D-H", "Q"]);
+        assert_eq!(ranges.folder("zebra.txt"), OTHER);
+        assert!(LetterRanges::parse("A-F,E-K").is_err());
+        assert!(LetterRanges::parse("C-A").is_err());
+        assert!(LetterRanges::parse("AB-C").is_err());
     }
 
-    Err(anyhow::anyhow!("Could not resolve name conflict for: {}", path.display()))
-}
\ No newline at end of file
+    #[test]
+    fn folders_of_names() {
+        let ranges = LetterRanges::even(4).unwrap();
+        assert_eq!(ranges.folder("Émile.txt"), "A-F");
+        assert_eq!(ranges.folder("Øresund.jpg"), "N-S");
+        assert_eq!(ranges.folder("７seas.txt"), DIGITS);
+        assert_eq!(ranges.folder("2024 report.pdf"), DIGITS);
+        assert_eq!(ranges.folder("½ price.txt"), OTHER);
+        assert_eq!(ranges.folder("Ⅻ.txt"), "T-Z");
+        assert_eq!(ranges.folder("Ωmega.txt"), "Greek");
+        assert_eq!(ranges.folder("日本.txt"), "CJK");
+        assert_eq!(ranges.folder("  "), OTHER);
+    }
+}
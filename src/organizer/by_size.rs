@@ -1,64 +1,249 @@
 use std::path::{Path, PathBuf};
 use anyhow::Result;
-use tokio::fs;
-use chrono::Utc;
-
-use super::{OrganizeAction, FileMove, log_action, handle_name_conflict};
-
-const KB: u64 = 1024;
-const MB: u64 = KB * 1024;
-const GB: u64 = MB * 1024;
-
-pub async fn organize_by_size(folder: &Path) -> Result<()> {
-    let mut entries = fs::read_dir(folder).await?;
-    let mut moves = Vec::new();
-
-    // Create folders for different sizes
-    let small_folder = folder.join("Small (0-1MB)");
-    let medium_folder = folder.join("Medium (1MB-100MB)");
-    let large_folder = folder.join("Large (100MB-1GB)");
-    let huge_folder = folder.join("Huge (1GB+)");
-
-    fs::create_dir_all(&small_folder).await?;
-    fs::create_dir_all(&medium_folder).await?;
-    fs::create_dir_all(&large_folder).await?;
-    fs::create_dir_all(&huge_folder).await?;
-
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if path.is_dir() || path.file_name().unwrap().to_string_lossy().starts_with('.') {
-            continue;
+use async_trait::async_trait;
+
+use super::rules::parse_size_in;
+use super::{Entry, ModeOption, ModeOptions, Organizer, Plan, Walk, collect_entries, plan_classified, plan_entries};
+
+static OPTIONS: &[ModeOption] = &[
+    ModeOption {
+        name: "buckets",
+        short: None,
+        value_name: "SIZES",
+        help: "Upper limits of the size folders, e.g. 10MB,500MB,4GB (files above the last one get a folder too)",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "labels",
+        short: None,
+        value_name: "NAMES",
+        help: "Folder names for --buckets, one more than there are limits, e.g. Small,Medium,Large,Huge",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "units",
+        short: None,
+        value_name: "UNITS",
+        help: "Size units of --buckets, the folder names and {size} in templates: binary (1KB = 1024 bytes) or si (1KB = 1000 bytes)",
+        default: Some("binary"),
+        flag: false,
+    },
+    ModeOption {
+        name: "percentiles",
+        short: None,
+        value_name: "N",
+        help: "Split the files into N folders holding about the same number of files each",
+        default: None,
+        flag: false,
+    },
+];
+
+/// Size classes, each holding the files smaller than its limit. The last
+/// class has no limit.
+struct Buckets {
+    limits: Vec<u64>,
+    labels: Vec<String>,
+}
+
+impl Buckets {
+    fn from_options(options: &ModeOptions) -> Result<Option<Self>> {
+        let step = units(options)?;
+        let Some(limits) = options.get::<String>("buckets") else {
+            if options.get::<String>("labels").is_some() {
+                anyhow::bail!("--labels needs --buckets");
+            }
+            return Ok(None);
+        };
+
+        let limits = limits.split(',')
+            .map(|limit| parse_size_in(limit, step))
+            .collect::<Result<Vec<_>>>()?;
+        if limits.is_empty() || limits.windows(2).any(|pair| pair[0] >= pair[1]) {
+            anyhow::bail!("--buckets has to list increasing sizes, e.g. 10MB,500MB,4GB");
         }
 
-        let metadata = fs::metadata(&path).await?;
-        let size = metadata.len();
-        let destination_folder = if size < MB {
-            &small_folder
-        } else if size < 100 * MB {
-            &medium_folder
-        } else if size < GB {
-            &large_folder
-        } else {
-            &huge_folder
+        let labels = match options.get::<String>("labels") {
+            Some(labels) => {
+                let labels: Vec<String> = labels.split(',').map(|label| label.trim().to_string()).collect();
+                if labels.len() != limits.len() + 1 || labels.iter().any(|label| label.is_empty()) {
+                    anyhow::bail!("--labels needs {} names for {} bucket limits", limits.len() + 1, limits.len());
+                }
+                labels
+            }
+            None => range_labels(&limits, step),
         };
+        Ok(Some(Buckets { limits, labels }))
+    }
 
-        let new_path = destination_folder.join(path.file_name().unwrap());
-        let new_path = handle_name_conflict(&new_path).await?;
+    fn label(&self, size: u64) -> &str {
+        let index = self.limits.iter().position(|&limit| size < limit).unwrap_or(self.limits.len());
+        &self.labels[index]
+    }
+}
 
-        fs::rename(&path, &new_path).await?;
-        moves.push(FileMove {
-            from: path,
-            to: new_path,
-        });
+/// Labels like `0-10MB`, `10MB-500MB` and `500MB+`
+fn range_labels(limits: &[u64], step: u64) -> Vec<String> {
+    let mut labels = Vec::with_capacity(limits.len() + 1);
+    let mut lower = "0".to_string();
+    for &limit in limits {
+        let upper = format_size(limit, step);
+        labels.push(format!("{}-{}", lower, upper));
+        lower = upper;
     }
+    labels.push(format!("{}+", lower));
+    labels
+}
 
-    let action = OrganizeAction {
-        timestamp: Utc::now(),
-        action_type: "by_size".to_string(),
-        moves,
-    };
-    log_action(folder, action.clone()).await?;
+/// Bytes per KB chosen with `--units`
+pub fn units(options: &ModeOptions) -> Result<u64> {
+    match options.get::<String>("units").as_deref() {
+        None | Some("binary") => Ok(1024),
+        Some("si") => Ok(1000),
+        Some(other) => anyhow::bail!("Unknown units '{}', use binary or si", other),
+    }
+}
 
-    println!("Successfully organized {} files by size", action.moves.len());
-    Ok(())
-}
\ No newline at end of file
+/// Short sizes for folder names like `1.5MB`
+fn format_size(bytes: u64, step: u64) -> String {
+    for (exponent, suffix) in [(4, "TB"), (3, "GB"), (2, "MB"), (1, "KB")] {
+        let unit = step.pow(exponent);
+        if bytes >= unit {
+            let value = bytes as f64 / unit as f64;
+            return if value.fract() == 0.0 {
+                format!("{}{}", value as u64, suffix)
+            } else {
+                format!("{:.1}{}", value, suffix)
+            };
+        }
+    }
+    format!("{}B", bytes)
+}
+
+/// Buckets that split `sizes` into `count` groups of about the same number of
+/// files, labeled with the sizes each group holds. Equal sizes never end up in
+/// different groups, so there may be fewer groups, down to a single one.
+fn percentile_buckets(sizes: &mut [u64], count: usize, step: u64) -> Option<Buckets> {
+    sizes.sort_unstable();
+    let &smallest = sizes.first()?;
+
+    let mut limits: Vec<u64> = (1..count).map(|k| sizes[k * sizes.len() / count]).collect();
+    limits.dedup();
+    limits.retain(|&limit| limit > smallest);
+
+    let total = limits.len() + 1;
+    let labels = (0..total)
+        .map(|index| {
+            let lower = if index == 0 { smallest } else { limits[index - 1] };
+            let upper = limits.get(index)
+                .map(|&limit| sizes[sizes.partition_point(|&size| size < limit) - 1])
+                .unwrap_or(sizes[sizes.len() - 1]);
+            let range = if lower == upper {
+                format_size(lower, step)
+            } else {
+                format!("{}-{}", format_size(lower, step), format_size(upper, step))
+            };
+            format!("{} of {} ({})", index + 1, total, range)
+        })
+        .collect();
+    Some(Buckets { limits, labels })
+}
+
+pub struct BySize;
+
+#[async_trait]
+impl Organizer for BySize {
+    fn name(&self) -> &'static str {
+        "size"
+    }
+
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
+    }
+
+    fn classify(&self, entry: &Entry, options: &ModeOptions) -> Option<PathBuf> {
+        let size = entry.metadata.len();
+        let destination_folder = match Buckets::from_options(options).ok()? {
+            Some(buckets) => buckets.label(size).to_string(),
+            None => size_bucket(size, units(options).ok()?),
+        };
+        Some(Path::new(&destination_folder).join(entry.path.file_name()?))
+    }
+
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        let Some(count) = options.get::<String>("percentiles") else {
+            Buckets::from_options(options)?;
+            return plan_entries(self, folder, options).await;
+        };
+
+        let count: usize = count.parse().ok().filter(|&count| count >= 2)
+            .ok_or_else(|| anyhow::anyhow!("--percentiles needs a number of folders of at least 2"))?;
+        if options.get::<String>("buckets").is_some() || options.get::<String>("labels").is_some() {
+            anyhow::bail!("--percentiles picks the buckets itself, leave out --buckets and --labels");
+        }
+
+        let mut sizes: Vec<u64> = collect_entries(folder, &Walk::from_options(self, folder, options).await?).await?
+            .iter()
+            .map(|entry| entry.metadata.len())
+            .collect();
+        let Some(buckets) = percentile_buckets(&mut sizes, count, units(options)?) else {
+            return Ok(Plan::new(self.action_type(options)));
+        };
+
+        plan_classified(self, folder, options, |entry| {
+            Some(Path::new(buckets.label(entry.metadata.len())).join(entry.path.file_name()?))
+        }).await
+    }
+}
+
+/// Folder name of the size class `size` falls into, with `step` bytes per KB
+pub fn size_bucket(size: u64, step: u64) -> String {
+    let limits = [step.pow(2), 100 * step.pow(2), step.pow(3)];
+    let index = limits.iter().position(|&limit| size < limit).unwrap_or(limits.len());
+    let name = ["Small", "Medium", "Large", "Huge"][index];
+    format!("{} ({})", name, range_labels(&limits, step)[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+    const GB: u64 = MB * 1024;
+
+    #[test]
+    fn percentiles_split_by_file_count() {
+        let mut sizes = vec![100, 1, 2, 3, 4, 5, 6, 7];
+        let buckets = percentile_buckets(&mut sizes, 4, 1024).unwrap();
+        assert_eq!(buckets.limits, [3, 5, 7]);
+        assert_eq!(buckets.labels, ["1 of 4 (1B-2B)", "2 of 4 (3B-4B)", "3 of 4 (5B-6B)", "4 of 4 (7B-100B)"]);
+        assert_eq!(buckets.label(4), "2 of 4 (3B-4B)");
+    }
+
+    #[test]
+    fn percentiles_of_equal_sizes_make_one_bucket() {
+        let mut sizes = vec![5; 10];
+        let buckets = percentile_buckets(&mut sizes, 4, 1024).unwrap();
+        assert!(buckets.limits.is_empty());
+        assert_eq!(buckets.label(5), "1 of 1 (5B)");
+        assert!(percentile_buckets(&mut [], 4, 1024).is_none());
+    }
+
+    #[test]
+    fn range_labels_and_sizes() {
+        assert_eq!(range_labels(&[10 * MB, 500 * MB], 1024), ["0-10MB", "10MB-500MB", "500MB+"]);
+        assert_eq!(format_size(1536, 1024), "1.5KB");
+        assert_eq!(format_size(2_000_000, 1000), "2MB");
+        assert_eq!(format_size(999, 1000), "999B");
+    }
+
+    #[test]
+    fn default_buckets_follow_the_units() {
+        assert_eq!(size_bucket(1_000_000, 1024), "Small (0-1MB)");
+        assert_eq!(size_bucket(1_000_000, 1000), "Medium (1MB-100MB)");
+        assert_eq!(size_bucket(GB, 1024), "Huge (1GB+)");
+        assert_eq!(size_bucket(GB, 1000), "Huge (1GB+)");
+        assert_eq!(size_bucket(999_999_999, 1000), "Large (100MB-1GB)");
+    }
+}
@@ -1,118 +1,138 @@
 use std::path::{Path, PathBuf};
 use std::collections::HashMap;
+use std::sync::LazyLock;
 use anyhow::Result;
-use tokio::fs;
-use chrono::Utc;
-
-use super::{OrganizeAction, FileMove, log_action};
-
-pub async fn organize_by_type(folder: &Path) -> Result<()> {
-    let mut entries = fs::read_dir(folder).await?;
-    let mut moves = Vec::new();
-    let mut type_folders: HashMap<String, PathBuf> = HashMap::new();
-
-    // Define file type mappings
-    let type_mappings = get_type_mappings();
-
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-
-        // Skip directories and hidden files
-        if path.is_dir() || path.file_name().unwrap().to_string_lossy().starts_with('.') {
-            continue;
-        }
-
-        if let Some(extension) = path.extension() {
-            let ext = extension.to_string_lossy().to_lowercase();
-            let file_type = type_mappings.get(&ext).unwrap_or(&"Others".to_string()).clone();
-
-            // Create type folder if it doesn't exist
-            let type_folder = folder.join(&file_type);
-            if !type_folders.contains_key(&file_type) {
-                if !type_folder.exists() {
-                    fs::create_dir(&type_folder).await?;
-                }
-                type_folders.insert(file_type.clone(), type_folder.clone());
-            }
-
-            // Move file
-            let new_path = type_folder.join(path.file_name().unwrap());
-            let new_path = handle_name_conflict(&new_path).await?;
-
-            fs::rename(&path, &new_path).await?;
-            moves.push(FileMove {
-                from: path,
-                to: new_path,
-            });
-        }
-    }
-
-    // Log the action
-    let action = OrganizeAction {
-        timestamp: Utc::now(),
-        action_type: "by_type".to_string(),
-        moves,
-    };
-    log_action(folder, action.clone()).await?;
-
-    println!("Successfully organized {} files by type", action.moves.len());
-    Ok(())
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::layout::sanitize;
+use super::report::{self, ReportFormat};
+use super::sniff::sniff;
+use super::{Entry, ModeOption, ModeOptions, Organizer, Walk, collect_entries, user_config_file};
+
+static OPTIONS: &[ModeOption] = &[ModeOption {
+    name: "report",
+    short: None,
+    value_name: "FORMAT",
+    help: "Only print a json, csv or html report of files whose content does not match their extension",
+    default: None,
+    flag: false,
+}];
+
+/// A file whose content is a different type than its extension says
+pub struct Mismatch {
+    pub path: PathBuf,
+    /// Lowercase extension, empty if the file has none
+    pub extension: String,
+    /// Usual extension of the detected content
+    pub detected: &'static str,
 }
 
-fn get_type_mappings() -> HashMap<String, String> {
-    let mut mappings = HashMap::new();
+pub struct ByType;
 
-    // Images
-    for ext in ["jpg", "jpeg", "png", "gif", "bmp", "tiff", "svg", "webp", "ico"] {
-        mappings.insert(ext.to_string(), "Images".to_string());
+#[async_trait]
+impl Organizer for ByType {
+    fn name(&self) -> &'static str {
+        "type"
     }
 
-    // Documents
-    for ext in ["pdf", "doc", "docx", "txt", "rtf", "odt", "xls", "xlsx", "ppt", "pptx"] {
-        mappings.insert(ext.to_string(), "Documents".to_string());
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
     }
 
-    // Videos
-    for ext in ["mp4", "avi", "mkv", "mov", "wmv", "flv", "webm", "m4v"] {
-        mappings.insert(ext.to_string(), "Videos".to_string());
+    /// Files without a known extension are sorted by their content, files
+    /// without an extension whose content isn't recognized stay where they are
+    fn classify(&self, entry: &Entry, _options: &ModeOptions) -> Option<PathBuf> {
+        let file_name = entry.path.file_name()?;
+        let file_type = match file_category(&file_name.to_string_lossy()) {
+            Some(category) => category.to_string(),
+            None => match sniff(&entry.path) {
+                Some(sniffed) => type_category(sniffed.extension),
+                None if entry.path.extension().is_some() => OTHERS.to_string(),
+                None => return None,
+            },
+        };
+
+        Some(Path::new(&file_type).join(file_name))
     }
 
-    // Audio
-    for ext in ["mp3", "wav", "flac", "aac", "ogg", "wma", "m4a"] {
-        mappings.insert(ext.to_string(), "Audio".to_string());
-    }
+    async fn report(&self, folder: &Path, format: &str, options: &ModeOptions) -> Result<String> {
+        let format: ReportFormat = format.parse()?;
+        let mut mismatches = Vec::new();
+
+        for entry in collect_entries(folder, &Walk::from_options(self, folder, options).await?).await? {
+            let Some(sniffed) = sniff(&entry.path) else {
+                continue;
+            };
+            let extension = entry.path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            if !sniffed.accepts(&extension) {
+                mismatches.push(Mismatch { path: entry.path, extension, detected: sniffed.extension });
+            }
+        }
+        mismatches.sort_by(|a, b| a.path.cmp(&b.path));
 
-    // Archives
-    for ext in ["zip", "rar", "7z", "tar", "gz", "bz2"] {
-        mappings.insert(ext.to_string(), "Archives".to_string());
+        report::render_mismatches(folder, &mismatches, format)
     }
+}
 
-    // Executables
-    for ext in ["exe", "msi", "deb", "rpm", "dmg", "app"] {
-        mappings.insert(ext.to_string(), "Executables".to_string());
-    }
+/// Category of files nothing more specific is known about
+pub const OTHERS: &str = "Others";
+
+/// The category table shipped with the organizer, see the file for the format
+const BUILT_IN_TYPES: &str = include_str!("types.toml");
 
-    mappings
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TypesFile {
+    #[serde(default)]
+    category: Vec<CategorySpec>,
 }
 
-async fn handle_name_conflict(path: &Path) -> Result<PathBuf> {
-    if !path.exists() {
-        return Ok(path.to_path_buf());
-    }
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CategorySpec {
+    name: String,
+    extensions: Vec<String>,
+}
 
-    let parent = path.parent().unwrap();
-    let filename = path.file_stem().unwrap().to_string_lossy();
-    let extension = path.extension()
-        .map(|e| format!(".{}", e.to_string_lossy()))
-        .unwrap_or_default();
-
-    for i in 1..1000 {
-        let new_name = format!("{} ({}){}", filename, i, extension);
-        let new_path = parent.join(new_name);
-        if !new_path.exists() {
-            return Ok(new_path);
+/// Lowercase extension, without the leading dot, to category. The user's
+/// `types.toml` is applied on top of the built-in table.
+static TYPES: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
+    let mut types = HashMap::new();
+    add_categories(&mut types, toml::from_str(BUILT_IN_TYPES).expect("built-in types.toml is valid"));
+
+    if let Some(path) = user_config_file("types.toml").filter(|p| p.is_file()) {
+        let file = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| toml::from_str(&content).map_err(anyhow::Error::from));
+        match file {
+            Ok(file) => add_categories(&mut types, file),
+            Err(e) => eprintln!("Warning: ignoring {}: {}", path.display(), e),
+        }
+    }
+    types
+});
+
+fn add_categories(types: &mut HashMap<String, String>, file: TypesFile) {
+    for category in file.category {
+        let name = sanitize(&category.name);
+        for extension in category.extensions {
+            types.insert(extension.trim_start_matches('.').to_lowercase(), name.clone());
         }
     }
+}
 
-    Err(anyhow::anyhow!("Could not resolve name conflict for: {}", path.display()))
-}
\ No newline at end of file
+/// Category folder for a lowercase extension, "Others" if it is not known
+pub fn type_category(ext: &str) -> String {
+    TYPES.get(ext).cloned().unwrap_or_else(|| OTHERS.to_string())
+}
+
+/// Category of a file name, trying the longest extension first so
+/// `backup.tar.gz` matches `tar.gz` before `gz`
+pub fn file_category(file_name: &str) -> Option<&'static str> {
+    let name = file_name.to_lowercase();
+    name.match_indices('.')
+        .filter(|(index, _)| *index > 0)
+        .find_map(|(index, _)| TYPES.get(&name[index + 1..]))
+        .map(String::as_str)
+}
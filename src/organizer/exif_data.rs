@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{In, Reader, Tag, Value};
+
+/// The EXIF fields the organizer cares about
+#[derive(Debug, Clone, Default)]
+pub struct ExifData {
+    /// When the picture was taken, in the camera's local time
+    pub taken: Option<NaiveDateTime>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+}
+
+/// Reads the EXIF block of a JPEG, TIFF, HEIF, PNG or WebP file. Returns
+/// `None` for other files and for files without EXIF data.
+pub fn read_exif(path: &Path) -> Option<ExifData> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = Reader::new().read_from_container(&mut reader).ok()?;
+
+    let ascii = |tag: Tag| -> Option<String> {
+        match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(values) => {
+                let text = String::from_utf8_lossy(values.first()?).trim().trim_end_matches('\0').to_string();
+                (!text.is_empty()).then_some(text)
+            }
+            _ => None,
+        }
+    };
+
+    let taken = [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| parse_datetime(&ascii(tag)?));
+
+    Some(ExifData { taken, make: ascii(Tag::Make), model: ascii(Tag::Model) })
+}
+
+/// EXIF dates look like `2024:03:17 14:02:55`
+fn parse_datetime(value: &str) -> Option<NaiveDateTime> {
+    let datetime = exif::DateTime::from_ascii(value.as_bytes()).ok()?;
+    NaiveDate::from_ymd_opt(datetime.year as i32, datetime.month as u32, datetime.day as u32)?
+        .and_hms_opt(datetime.hour as u32, datetime.minute as u32, datetime.second as u32)
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::{hide_file, write_atomic};
+
+/// What a digest was computed from. A file whose size or modification time
+/// changed since then is hashed again.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct CachedHash {
+    len: u64,
+    modified: SystemTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    partial: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    full: Option<String>,
+}
+
+/// Which digest of a file is looked up
+#[derive(Debug, Clone, Copy)]
+pub enum HashKind {
+    /// First and last block only
+    Partial,
+    Full,
+}
+
+/// Contents of `.organize_hash_cache.json`, keyed by path relative to the folder.
+/// Files outside of it, like a reference library, are keyed by their full path.
+#[derive(Debug, Default)]
+pub struct HashCache {
+    folder: PathBuf,
+    entries: HashMap<PathBuf, CachedHash>,
+    /// Entries looked up or hashed during this run
+    seen: HashMap<PathBuf, CachedHash>,
+    enabled: bool,
+}
+
+pub fn get_cache_path(folder: &Path) -> PathBuf {
+    folder.join(".organize_hash_cache.json")
+}
+
+impl HashCache {
+    /// Loads the cache of `folder`. A missing or unreadable cache starts empty.
+    pub async fn load(folder: &Path) -> Self {
+        let entries = match fs::read_to_string(get_cache_path(folder)).await {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => HashMap::new(),
+        };
+        HashCache { folder: folder.to_path_buf(), entries, seen: HashMap::new(), enabled: true }
+    }
+
+    /// A cache that never returns anything and is never written, for `--no-cache`
+    pub fn disabled() -> Self {
+        HashCache::default()
+    }
+
+    /// Returns the stored digest if the file still has the size and
+    /// modification time it had when it was hashed
+    pub fn get(&mut self, path: &Path, kind: HashKind) -> Option<String> {
+        let key = self.key(path)?;
+        let (len, modified) = file_stamp(path)?;
+
+        let cached = self.entries.get(&key).filter(|c| c.len == len && c.modified == modified)?;
+        let digest = match kind {
+            HashKind::Partial => cached.partial.clone(),
+            HashKind::Full => cached.full.clone(),
+        }?;
+
+        self.seen.insert(key, cached.clone());
+        Some(digest)
+    }
+
+    pub fn insert(&mut self, path: &Path, kind: HashKind, digest: String) {
+        let (Some(key), Some((len, modified))) = (self.key(path), file_stamp(path)) else {
+            return;
+        };
+
+        let stale = |c: &CachedHash| c.len != len || c.modified != modified;
+        let entry = self.seen.entry(key.clone())
+            .or_insert_with(|| self.entries.get(&key).cloned().unwrap_or(CachedHash {
+                len, modified, partial: None, full: None,
+            }));
+        if stale(entry) {
+            *entry = CachedHash { len, modified, partial: None, full: None };
+        }
+
+        match kind {
+            HashKind::Partial => entry.partial = Some(digest),
+            HashKind::Full => entry.full = Some(digest),
+        }
+    }
+
+    /// Writes back the cache with the entries of this run, dropping files that
+    /// are gone. Files this run didn't look at, e.g. because of `--include`,
+    /// keep their entries.
+    pub async fn save(&self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let mut entries: HashMap<PathBuf, CachedHash> = self.entries.iter()
+            .filter(|(key, _)| self.folder.join(key).exists())
+            .map(|(key, cached)| (key.clone(), cached.clone()))
+            .collect();
+        entries.extend(self.seen.iter().map(|(key, cached)| (key.clone(), cached.clone())));
+
+        let cache_path = get_cache_path(&self.folder);
+        if entries.is_empty() {
+            let _ = fs::remove_file(&cache_path).await;
+            return Ok(());
+        }
+
+        write_atomic(&cache_path, serde_json::to_string(&entries)?.as_bytes()).await?;
+        hide_file(&cache_path);
+        Ok(())
+    }
+
+    fn key(&self, path: &Path) -> Option<PathBuf> {
+        if !self.enabled {
+            return None;
+        }
+        Some(path.strip_prefix(&self.folder).unwrap_or(path).to_path_buf())
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
@@ -0,0 +1,117 @@
+use std::fs::Metadata;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::Result;
+
+use super::ModeOptions;
+
+/// Files the operating system keeps next to the user's files. They are never
+/// moved, whatever the `--hidden` policy says.
+const OS_METADATA: &[&str] = &[
+    "Thumbs.db",
+    "ehthumbs.db",
+    "ehthumbs_vista.db",
+    "desktop.ini",
+    "$RECYCLE.BIN",
+    "System Volume Information",
+    ".DS_Store",
+    ".localized",
+    ".Spotlight-V100",
+    ".Trashes",
+    ".fseventsd",
+    ".TemporaryItems",
+    ".DocumentRevisions-V100",
+    ".AppleDouble",
+    "Icon\r",
+    ".directory",
+];
+
+/// The organizer's own bookkeeping, which has to stay where it is for undo,
+/// the hash cache and the rules to keep working
+const OWN_FILES: &[&str] = &[
+    ".organize_log.json",
+    ".organize_log.json.tmp",
+    ".organize_log.json.bak",
+    ".organize_journal.jsonl",
+    ".organize_hash_cache.json",
+    ".organize_hash_cache.json.tmp",
+    ".organize_quarantine",
+    ".organizeignore",
+    ".organize.toml",
+    ".organize.json",
+];
+
+/// Which hidden files and folders the walkers leave alone
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HiddenPolicy {
+    /// Names starting with a dot
+    SkipDotfiles,
+    /// Dot files and everything marked hidden or system
+    #[default]
+    SkipHidden,
+    /// Only what is marked as a system file
+    SkipSystem,
+    /// Everything but the OS metadata and the organizer's own files
+    IncludeAll,
+}
+
+impl FromStr for HiddenPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dotfiles" => Ok(HiddenPolicy::SkipDotfiles),
+            "hidden" => Ok(HiddenPolicy::SkipHidden),
+            "system" => Ok(HiddenPolicy::SkipSystem),
+            "none" => Ok(HiddenPolicy::IncludeAll),
+            other => anyhow::bail!("Unknown --hidden '{}', use hidden, dotfiles, system or none", other),
+        }
+    }
+}
+
+impl HiddenPolicy {
+    pub fn from_options(options: &ModeOptions) -> Result<Self> {
+        options.get::<String>("hidden").map_or(Ok(HiddenPolicy::default()), |policy| policy.parse())
+    }
+
+    /// Whether the file or folder at `path` is left out
+    pub fn skips(&self, path: &Path, metadata: &Metadata) -> bool {
+        let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+            return false;
+        };
+        if is_reserved(&name) {
+            return true;
+        }
+
+        let (hidden, system) = attributes(metadata);
+        match self {
+            HiddenPolicy::SkipDotfiles => name.starts_with('.'),
+            HiddenPolicy::SkipHidden => name.starts_with('.') || hidden || system,
+            HiddenPolicy::SkipSystem => system,
+            HiddenPolicy::IncludeAll => false,
+        }
+    }
+}
+
+/// OS metadata, AppleDouble `._` files and the organizer's own files
+pub fn is_reserved(name: &str) -> bool {
+    name.starts_with("._")
+        || name.ends_with(".organize_link")
+        || OS_METADATA.iter().chain(OWN_FILES).any(|reserved| reserved.eq_ignore_ascii_case(name))
+}
+
+/// The hidden and system attributes. Other systems have neither, a leading
+/// dot is all there is.
+#[cfg(windows)]
+fn attributes(metadata: &Metadata) -> (bool, bool) {
+    use std::os::windows::fs::MetadataExt;
+    use winapi::um::winnt::{FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM};
+
+    let attributes = metadata.file_attributes();
+    (attributes & FILE_ATTRIBUTE_HIDDEN != 0, attributes & FILE_ATTRIBUTE_SYSTEM != 0)
+}
+
+#[cfg(not(windows))]
+fn attributes(_metadata: &Metadata) -> (bool, bool) {
+    (false, false)
+}
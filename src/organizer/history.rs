@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::{OrganizeAction, hide_file, write_atomic};
+
+/// Number of actions kept in the log, for both the undo and the redo stack
+const MAX_ACTIONS: usize = 10;
+
+/// Contents of `.organize_log.json`
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct History {
+    #[serde(default)]
+    next_id: u64,
+    /// Executed actions, most recent last
+    #[serde(default)]
+    pub actions: Vec<OrganizeAction>,
+    /// Undone actions that can be redone, most recently undone last
+    #[serde(default)]
+    pub redo: Vec<OrganizeAction>,
+}
+
+/// Logs written before ids were introduced are a plain list of actions
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LogFile {
+    History(History),
+    Legacy(Vec<OrganizeAction>),
+}
+
+pub fn get_log_path(folder: &Path) -> PathBuf {
+    folder.join(".organize_log.json")
+}
+
+/// Where a log that could not be read is kept
+fn get_backup_path(folder: &Path) -> PathBuf {
+    folder.join(".organize_log.json.bak")
+}
+
+impl History {
+    pub async fn load(folder: &Path) -> Result<Self> {
+        let log_path = get_log_path(folder);
+        if !log_path.exists() {
+            return Ok(History::default());
+        }
+
+        let content = fs::read_to_string(&log_path).await?;
+        let mut history = match serde_json::from_str(&content) {
+            Ok(LogFile::History(history)) => history,
+            Ok(LogFile::Legacy(actions)) => History { next_id: 0, actions, redo: Vec::new() },
+            Err(e) => {
+                // Keep the damaged log for inspection instead of overwriting it
+                let backup = get_backup_path(folder);
+                fs::rename(&log_path, &backup).await
+                    .with_context(|| format!("{} is damaged ({}) and could not be moved aside", log_path.display(), e))?;
+                eprintln!("Warning: {} is damaged ({}), moved it to {} and started a new history",
+                    log_path.display(), e, backup.display());
+                History::default()
+            }
+        };
+
+        // Give legacy entries an id so they can be addressed by `undo --id`
+        for action in history.actions.iter_mut().chain(history.redo.iter_mut()) {
+            if action.id == 0 {
+                history.next_id += 1;
+                action.id = history.next_id;
+            }
+        }
+        Ok(history)
+    }
+
+    pub async fn save(&self, folder: &Path) -> Result<()> {
+        let log_path = get_log_path(folder);
+        if self.actions.is_empty() && self.redo.is_empty() {
+            let _ = fs::remove_file(&log_path).await;
+            return Ok(());
+        }
+
+        let content = serde_json::to_string_pretty(self)?;
+        write_atomic(&log_path, content.as_bytes()).await?;
+        hide_file(&log_path);
+        Ok(())
+    }
+
+    /// Records a new action. Anything undone before can no longer be redone.
+    pub fn push(&mut self, mut action: OrganizeAction) {
+        self.next_id += 1;
+        action.id = self.next_id;
+
+        self.actions.push(action);
+        self.redo.clear();
+
+        // Keep only last 10 actions to prevent log from growing too large
+        if self.actions.len() > MAX_ACTIONS {
+            self.actions.drain(..self.actions.len() - MAX_ACTIONS);
+        }
+    }
+
+    /// Moves the most recent action onto the redo stack
+    pub fn pop_undone(&mut self) -> Option<OrganizeAction> {
+        let action = self.actions.pop()?;
+        self.redo.push(action.clone());
+        if self.redo.len() > MAX_ACTIONS {
+            self.redo.remove(0);
+        }
+        Some(action)
+    }
+
+    /// Moves the most recently undone action back onto the action list
+    pub fn pop_redone(&mut self) -> Option<OrganizeAction> {
+        let action = self.redo.pop()?;
+        self.actions.push(action.clone());
+        Some(action)
+    }
+}
+
+pub async fn log_action(folder: &Path, action: OrganizeAction) -> Result<()> {
+    let mut history = History::load(folder).await?;
+    history.push(action);
+    history.save(folder).await
+}
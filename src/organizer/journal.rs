@@ -0,0 +1,526 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use super::link::{replace_with_copy, replace_with_link};
+use super::{History, OrganizeAction, Plan, hide_file, log_action, move_file, quarantine};
+
+/// How to deal with an operation that was interrupted halfway through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Carry out the remaining steps of the plan
+    Finish,
+    /// Move the already moved files back
+    Rollback,
+}
+
+/// One line of the journal file. The plan is written before the first file is
+/// touched, then every completed step is appended.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    Begin { timestamp: DateTime<Utc>, plan: Box<Plan> },
+    Moved { index: usize },
+    /// The source of a move was gone by the time it was reached
+    Skipped { index: usize },
+    Deleted { index: usize },
+    Linked { index: usize },
+}
+
+pub fn get_journal_path(folder: &Path) -> PathBuf {
+    folder.join(".organize_journal.jsonl")
+}
+
+/// Write-ahead journal of a running operation
+pub struct Journal {
+    path: PathBuf,
+    file: fs::File,
+    /// Also the timestamp of the logged action, which tells recovery whether
+    /// the action made it into the log
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Journal {
+    pub async fn begin(folder: &Path, plan: &Plan) -> Result<Self> {
+        let path = get_journal_path(folder);
+        let file = fs::File::create(&path).await?;
+        hide_file(&path);
+
+        let mut journal = Journal { path, file, timestamp: Utc::now() };
+        journal.write(&Record::Begin { timestamp: journal.timestamp, plan: Box::new(plan.clone()) }).await?;
+        Ok(journal)
+    }
+
+    pub async fn moved(&mut self, index: usize) -> Result<()> {
+        self.write(&Record::Moved { index }).await
+    }
+
+    pub async fn skipped(&mut self, index: usize) -> Result<()> {
+        self.write(&Record::Skipped { index }).await
+    }
+
+    pub async fn deleted(&mut self, index: usize) -> Result<()> {
+        self.write(&Record::Deleted { index }).await
+    }
+
+    pub async fn linked(&mut self, index: usize) -> Result<()> {
+        self.write(&Record::Linked { index }).await
+    }
+
+    /// Removes the journal once the operation has been logged
+    pub async fn finish(self) -> Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path).await?;
+        Ok(())
+    }
+
+    async fn write(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).await?;
+        // The record has to be on disk before the next step starts
+        self.file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// An operation found in the journal that never completed
+pub struct Interrupted {
+    pub timestamp: DateTime<Utc>,
+    pub plan: Plan,
+    moved: HashSet<usize>,
+    skipped: HashSet<usize>,
+    deleted: HashSet<usize>,
+    linked: HashSet<usize>,
+}
+
+impl Interrupted {
+    pub fn completed_moves(&self) -> usize {
+        self.plan.moves.iter().enumerate()
+            .filter(|(i, m)| self.is_moved(*i, &m.from, &m.to))
+            .count()
+    }
+
+    /// A crash between a rename and its journal record leaves the move
+    /// unrecorded, so the files themselves have the final say
+    fn is_moved(&self, index: usize, from: &Path, to: &Path) -> bool {
+        self.moved.contains(&index) || (to.exists() && !from.exists())
+    }
+}
+
+/// Reads the journal left behind by an interrupted operation, if any
+pub async fn find_interrupted(folder: &Path) -> Result<Option<Interrupted>> {
+    let path = get_journal_path(folder);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&path).await?;
+    let mut interrupted: Option<Interrupted> = None;
+
+    // A torn last line just means the step after it never happened
+    for record in content.lines().filter_map(|line| serde_json::from_str::<Record>(line).ok()) {
+        match record {
+            Record::Begin { timestamp, plan } => {
+                interrupted = Some(Interrupted {
+                    timestamp,
+                    plan: *plan,
+                    moved: HashSet::new(),
+                    skipped: HashSet::new(),
+                    deleted: HashSet::new(),
+                    linked: HashSet::new(),
+                });
+            }
+            Record::Moved { index } => {
+                if let Some(i) = interrupted.as_mut() {
+                    i.moved.insert(index);
+                }
+            }
+            Record::Skipped { index } => {
+                if let Some(i) = interrupted.as_mut() {
+                    i.skipped.insert(index);
+                }
+            }
+            Record::Deleted { index } => {
+                if let Some(i) = interrupted.as_mut() {
+                    i.deleted.insert(index);
+                }
+            }
+            Record::Linked { index } => {
+                if let Some(i) = interrupted.as_mut() {
+                    i.linked.insert(index);
+                }
+            }
+        }
+    }
+
+    if interrupted.is_none() {
+        // Died before the plan was written, nothing was touched
+        fs::remove_file(&path).await?;
+    }
+
+    Ok(interrupted)
+}
+
+/// Checks for an interrupted operation and finishes or rolls it back.
+/// Returns `false` if the user chose to leave it alone.
+pub async fn recover(folder: &Path, choice: Option<Recovery>) -> Result<bool> {
+    let Some(interrupted) = find_interrupted(folder).await? else {
+        return Ok(true);
+    };
+
+    // A crash after logging but before the journal was removed leaves
+    // nothing to finish, logging the action again would undo it twice
+    let history = History::load(folder).await?;
+    if history.actions.iter().chain(&history.redo).any(|action| action.timestamp == interrupted.timestamp) {
+        fs::remove_file(get_journal_path(folder)).await?;
+        return Ok(true);
+    }
+
+    let message = format!(
+        "The {} operation started at {} was interrupted after {} of {} file moves.\n\n\
+         Yes: finish the operation\nNo: roll back the moves already made\nCancel: leave the folder as it is",
+        interrupted.plan.action_type,
+        interrupted.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        interrupted.completed_moves(),
+        interrupted.plan.moves.len(),
+    );
+
+    match choice.or_else(|| ask_recovery(&message)) {
+        Some(Recovery::Finish) => finish(folder, interrupted).await?,
+        Some(Recovery::Rollback) => rollback(interrupted).await?,
+        None => return Ok(false),
+    }
+
+    fs::remove_file(get_journal_path(folder)).await?;
+    Ok(true)
+}
+
+async fn finish(folder: &Path, interrupted: Interrupted) -> Result<()> {
+    let mut plan = interrupted.plan.clone();
+
+    for dir in &plan.create_dirs {
+        fs::create_dir_all(dir).await?;
+        quarantine::hide_root(dir);
+    }
+
+    let mut moves = Vec::new();
+    for (index, file_move) in plan.moves.iter().enumerate() {
+        if interrupted.is_moved(index, &file_move.from, &file_move.to) {
+            moves.push(file_move.clone());
+            continue;
+        }
+        if interrupted.skipped.contains(&index) || !file_move.from.exists() {
+            continue;
+        }
+
+        match move_file(&file_move.from, &file_move.to).await {
+            Ok(()) => moves.push(file_move.clone()),
+            Err(e) => eprintln!("Failed to move {} to {}: {}", file_move.from.display(), file_move.to.display(), e),
+        }
+    }
+
+    let mut deletes = Vec::new();
+    for (index, path) in plan.deletes.iter().enumerate() {
+        if interrupted.deleted.contains(&index) || !path.exists() || fs::remove_file(path).await.is_ok() {
+            deletes.push(path.clone());
+        }
+    }
+
+    // Linking an already linked file again does no harm
+    let mut links = Vec::new();
+    for (index, link) in plan.links.iter().enumerate() {
+        if interrupted.linked.contains(&index) {
+            links.push(link.clone());
+            continue;
+        }
+
+        match replace_with_link(link).await {
+            Ok(()) => links.push(link.clone()),
+            Err(e) => eprintln!("Could not replace {} with a {}, leaving it as is: {}", link.path.display(), link.kind, e),
+        }
+    }
+
+    for dir in &plan.remove_dirs {
+        let _ = fs::remove_dir(dir).await;
+    }
+
+    println!("Finished interrupted {} operation: {} files moved", plan.action_type, moves.len());
+
+    plan.moves = moves;
+    plan.deletes = deletes;
+    plan.links = links;
+    log_action(folder, OrganizeAction { id: 0, timestamp: interrupted.timestamp, plan }).await
+}
+
+async fn rollback(interrupted: Interrupted) -> Result<()> {
+    let mut restored = 0;
+
+    for (index, link) in interrupted.plan.links.iter().enumerate().rev() {
+        if !interrupted.linked.contains(&index) {
+            continue;
+        }
+        match replace_with_copy(link).await {
+            Ok(()) => restored += 1,
+            Err(e) => eprintln!("Failed to turn {} back into a copy: {}", link.path.display(), e),
+        }
+    }
+
+    for (index, file_move) in interrupted.plan.moves.iter().enumerate().rev() {
+        if !interrupted.is_moved(index, &file_move.from, &file_move.to) {
+            continue;
+        }
+
+        if let Some(original_dir) = file_move.from.parent() {
+            fs::create_dir_all(original_dir).await?;
+        }
+        match move_file(&file_move.to, &file_move.from).await {
+            Ok(()) => restored += 1,
+            Err(e) => eprintln!("Failed to move {} back to {}: {}", file_move.to.display(), file_move.from.display(), e),
+        }
+    }
+
+    for dir in interrupted.plan.create_dirs.iter().rev() {
+        let _ = fs::remove_dir(dir).await;
+    }
+
+    println!("Rolled back interrupted {} operation: {} files restored", interrupted.plan.action_type, restored);
+    if !interrupted.deleted.is_empty() {
+        println!("{} deleted files cannot be restored", interrupted.deleted.len());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn ask_recovery(message: &str) -> Option<Recovery> {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::ptr;
+    use winapi::um::winuser::{MessageBoxW, IDNO, IDYES, MB_ICONWARNING, MB_YESNOCANCEL};
+
+    let to_wide = |s: &str| -> Vec<u16> {
+        OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+    };
+    let text = to_wide(message);
+    let caption = to_wide("Interrupted organization");
+
+    match unsafe { MessageBoxW(ptr::null_mut(), text.as_ptr(), caption.as_ptr(), MB_YESNOCANCEL | MB_ICONWARNING) } {
+        IDYES => Some(Recovery::Finish),
+        IDNO => Some(Recovery::Rollback),
+        _ => None,
+    }
+}
+
+#[cfg(not(windows))]
+fn ask_recovery(message: &str) -> Option<Recovery> {
+    use std::io::{self, Write};
+
+    println!("{}", message);
+    print!("Choose (y/n/c): ");
+    io::stdout().flush().ok()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).ok()?;
+    match input.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(Recovery::Finish),
+        "n" | "no" => Some(Recovery::Rollback),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::link::{FileLink, LinkKind, is_same_file};
+
+    /// An empty folder of its own for every test
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("organizer-journal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// Moves `a.txt` and `b.txt` into `Docs`
+    fn two_moves(folder: &Path) -> Plan {
+        let mut plan = Plan::new("by_type");
+        for name in ["a.txt", "b.txt"] {
+            std::fs::write(folder.join(name), name).unwrap();
+            plan.add_move(folder.join(name), folder.join("Docs").join(name)).unwrap();
+        }
+        plan
+    }
+
+    /// Writes the journal of `plan` with the given step records, followed by `tail`
+    fn write_journal(folder: &Path, plan: &Plan, steps: &[Record], tail: &str) {
+        let mut content = String::new();
+        let begin = Record::Begin { timestamp: Utc::now(), plan: Box::new(plan.clone()) };
+        for record in std::iter::once(&begin).chain(steps) {
+            content.push_str(&serde_json::to_string(record).unwrap());
+            content.push('\n');
+        }
+        content.push_str(tail);
+        std::fs::write(get_journal_path(folder), content).unwrap();
+    }
+
+    /// Carries out the first move of `plan` the way `execute` would
+    fn move_first(plan: &Plan) {
+        std::fs::create_dir_all(plan.moves[0].to.parent().unwrap()).unwrap();
+        std::fs::rename(&plan.moves[0].from, &plan.moves[0].to).unwrap();
+    }
+
+    #[tokio::test]
+    async fn torn_last_record_is_ignored() {
+        let folder = temp_folder("torn");
+        let plan = two_moves(&folder);
+        move_first(&plan);
+        write_journal(&folder, &plan, &[Record::Moved { index: 0 }], "{\"record\":\"mov");
+
+        let interrupted = find_interrupted(&folder).await.unwrap().unwrap();
+        assert_eq!(interrupted.moved, HashSet::from([0]));
+        assert_eq!(interrupted.completed_moves(), 1);
+        assert_eq!(interrupted.plan.moves.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn journal_without_plan_is_discarded() {
+        let folder = temp_folder("no-plan");
+        std::fs::write(get_journal_path(&folder), "{\"record\":\"be").unwrap();
+
+        assert!(find_interrupted(&folder).await.unwrap().is_none());
+        assert!(!get_journal_path(&folder).exists());
+    }
+
+    #[tokio::test]
+    async fn finish_completes_partial_move() {
+        let folder = temp_folder("finish");
+        let plan = two_moves(&folder);
+        move_first(&plan);
+        write_journal(&folder, &plan, &[Record::Moved { index: 0 }], "");
+
+        assert!(recover(&folder, Some(Recovery::Finish)).await.unwrap());
+
+        assert!(folder.join("Docs/a.txt").exists() && folder.join("Docs/b.txt").exists());
+        assert!(!folder.join("a.txt").exists() && !folder.join("b.txt").exists());
+        assert!(!get_journal_path(&folder).exists());
+        let history = History::load(&folder).await.unwrap();
+        assert_eq!(history.actions.len(), 1);
+        assert_eq!(history.actions[0].plan.moves.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn finish_counts_unrecorded_move() {
+        // Crashed after the rename but before its record was written
+        let folder = temp_folder("unrecorded");
+        let plan = two_moves(&folder);
+        move_first(&plan);
+        write_journal(&folder, &plan, &[], "");
+
+        recover(&folder, Some(Recovery::Finish)).await.unwrap();
+
+        let history = History::load(&folder).await.unwrap();
+        assert_eq!(history.actions[0].plan.moves.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn finish_after_logging_does_not_log_again() {
+        let folder = temp_folder("logged");
+        let plan = two_moves(&folder);
+        write_journal(&folder, &plan, &[], "");
+        let interrupted = find_interrupted(&folder).await.unwrap().unwrap();
+        log_action(&folder, OrganizeAction { id: 0, timestamp: interrupted.timestamp, plan: plan.clone() }).await.unwrap();
+
+        assert!(recover(&folder, Some(Recovery::Finish)).await.unwrap());
+
+        assert!(!get_journal_path(&folder).exists());
+        assert_eq!(History::load(&folder).await.unwrap().actions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn finish_skips_vanished_source() {
+        let folder = temp_folder("vanished");
+        let plan = two_moves(&folder);
+        std::fs::remove_file(&plan.moves[1].from).unwrap();
+        write_journal(&folder, &plan, &[Record::Skipped { index: 1 }], "");
+
+        recover(&folder, Some(Recovery::Finish)).await.unwrap();
+
+        assert!(folder.join("Docs/a.txt").exists());
+        let history = History::load(&folder).await.unwrap();
+        assert_eq!(history.actions[0].plan.moves.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_partial_move() {
+        let folder = temp_folder("rollback");
+        let plan = two_moves(&folder);
+        move_first(&plan);
+        write_journal(&folder, &plan, &[Record::Moved { index: 0 }], "");
+
+        assert!(recover(&folder, Some(Recovery::Rollback)).await.unwrap());
+
+        assert_eq!(std::fs::read_to_string(folder.join("a.txt")).unwrap(), "a.txt");
+        assert!(folder.join("b.txt").exists());
+        assert!(!folder.join("Docs").exists());
+        assert!(!get_journal_path(&folder).exists());
+        assert!(History::load(&folder).await.unwrap().actions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleted_and_linked_records() {
+        let folder = temp_folder("links");
+        let kept = folder.join("kept.txt");
+        let linked = folder.join("linked.txt");
+        let deleted = folder.join("deleted.txt");
+        let pending = folder.join("pending.txt");
+        std::fs::write(&kept, "same").unwrap();
+        std::fs::hard_link(&kept, &linked).unwrap();
+        std::fs::write(&pending, "same").unwrap();
+
+        let link = |path: &Path| FileLink {
+            path: path.to_path_buf(),
+            target: kept.clone(),
+            kind: LinkKind::Hard,
+            hash: None,
+            note: None,
+        };
+        let mut plan = Plan::new("remove_duplicates");
+        plan.deletes.push(deleted.clone());
+        plan.links.push(link(&linked));
+        plan.links.push(link(&pending));
+        write_journal(&folder, &plan, &[Record::Deleted { index: 0 }, Record::Linked { index: 0 }], "");
+
+        let interrupted = find_interrupted(&folder).await.unwrap().unwrap();
+        assert_eq!(interrupted.deleted, HashSet::from([0]));
+        assert_eq!(interrupted.linked, HashSet::from([0]));
+
+        recover(&folder, Some(Recovery::Finish)).await.unwrap();
+
+        assert!(is_same_file(&kept, &pending));
+        let logged = &History::load(&folder).await.unwrap().actions[0].plan;
+        assert_eq!(logged.deletes, vec![deleted]);
+        assert_eq!(logged.links.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn rollback_turns_links_back_into_copies() {
+        let folder = temp_folder("unlink");
+        let kept = folder.join("kept.txt");
+        let linked = folder.join("linked.txt");
+        std::fs::write(&kept, "same").unwrap();
+        std::fs::hard_link(&kept, &linked).unwrap();
+
+        let mut plan = Plan::new("remove_duplicates");
+        plan.links.push(FileLink { path: linked.clone(), target: kept.clone(), kind: LinkKind::Hard, hash: None, note: None });
+        write_journal(&folder, &plan, &[Record::Linked { index: 0 }], "");
+
+        recover(&folder, Some(Recovery::Rollback)).await.unwrap();
+
+        assert!(!is_same_file(&kept, &linked));
+        assert_eq!(std::fs::read_to_string(&linked).unwrap(), "same");
+    }
+}
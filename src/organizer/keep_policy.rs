@@ -0,0 +1,142 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Decides which file of a duplicate group stays in place
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeepPolicy {
+    /// Oldest modification time
+    Oldest,
+    /// Newest modification time
+    Newest,
+    /// Fewest path components, then fewest characters
+    ShortestPath,
+    /// Fewest "(1)", "Copy of" and similar markers in the file name
+    FewestMarkers,
+    /// A file inside the given directory
+    PreferredDir(PathBuf),
+    /// Longest file name, usually the original descriptive one
+    LongestName,
+}
+
+impl KeepPolicy {
+    /// Parses a `--keep` value, `preferred_dir` takes its directory from `--prefer`
+    pub fn parse(value: &str, prefer: Option<PathBuf>) -> anyhow::Result<Self> {
+        match value {
+            "preferred_dir" => prefer
+                .map(KeepPolicy::PreferredDir)
+                .ok_or_else(|| anyhow::anyhow!("--keep preferred_dir needs --prefer <DIR>")),
+            other => other.parse(),
+        }
+    }
+
+    /// Returns the index of the file to keep and why it was chosen
+    pub fn choose(&self, paths: &[PathBuf]) -> (usize, String) {
+        let candidates: Vec<Candidate> = paths.iter().map(|p| Candidate::new(p)).collect();
+
+        let best = (0..candidates.len())
+            .min_by(|&a, &b| {
+                self.compare(&candidates[a], &candidates[b])
+                    .then_with(|| tie_break(&candidates[a], &candidates[b]))
+            })
+            .unwrap_or(0);
+
+        let reason = match self {
+            KeepPolicy::Oldest => "oldest modification time".to_string(),
+            KeepPolicy::Newest => "newest modification time".to_string(),
+            KeepPolicy::ShortestPath => "shortest path".to_string(),
+            KeepPolicy::FewestMarkers => "fewest copy markers in the name".to_string(),
+            KeepPolicy::PreferredDir(dir) if candidates[best].path.starts_with(dir) => {
+                format!("inside preferred directory {}", dir.display())
+            }
+            KeepPolicy::PreferredDir(_) => "no copy in the preferred directory, fewest copy markers".to_string(),
+            KeepPolicy::LongestName => "longest file name".to_string(),
+        };
+
+        (best, reason)
+    }
+
+    fn compare(&self, a: &Candidate, b: &Candidate) -> Ordering {
+        match self {
+            KeepPolicy::Oldest => a.modified.cmp(&b.modified),
+            KeepPolicy::Newest => b.modified.cmp(&a.modified),
+            KeepPolicy::ShortestPath => a.path_len().cmp(&b.path_len()),
+            KeepPolicy::FewestMarkers => a.markers.cmp(&b.markers),
+            // `true` sorts after `false`, so compare the other way round
+            KeepPolicy::PreferredDir(dir) => b.path.starts_with(dir).cmp(&a.path.starts_with(dir)),
+            KeepPolicy::LongestName => b.name_len().cmp(&a.name_len()),
+        }
+    }
+}
+
+impl FromStr for KeepPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "newest" => Ok(KeepPolicy::Newest),
+            "shortest_path" => Ok(KeepPolicy::ShortestPath),
+            "fewest_markers" => Ok(KeepPolicy::FewestMarkers),
+            "longest_name" => Ok(KeepPolicy::LongestName),
+            _ => Err(anyhow::anyhow!(
+                "Invalid keep policy '{}', use oldest, newest, shortest_path, fewest_markers, preferred_dir or longest_name",
+                value
+            )),
+        }
+    }
+}
+
+struct Candidate<'a> {
+    path: &'a Path,
+    modified: Option<SystemTime>,
+    markers: usize,
+}
+
+impl<'a> Candidate<'a> {
+    fn new(path: &'a Path) -> Self {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        Candidate { path, modified, markers: count_copy_markers(&name) }
+    }
+
+    fn path_len(&self) -> (usize, usize) {
+        (self.path.components().count(), self.path.as_os_str().len())
+    }
+
+    fn name_len(&self) -> usize {
+        self.path.file_name().map(|n| n.to_string_lossy().chars().count()).unwrap_or(0)
+    }
+}
+
+/// Makes the choice independent of directory enumeration order
+fn tie_break(a: &Candidate, b: &Candidate) -> Ordering {
+    a.markers.cmp(&b.markers)
+        .then_with(|| a.path_len().cmp(&b.path_len()))
+        .then_with(|| a.path.cmp(b.path))
+}
+
+/// Counts the markers Explorer, browsers and sync tools add to copied files
+fn count_copy_markers(name: &str) -> usize {
+    let lower = name.to_lowercase();
+    let stem = lower.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&lower);
+
+    let mut markers = ["copy of ", "- copy", "_copy"]
+        .iter()
+        .map(|marker| stem.matches(marker).count())
+        .sum::<usize>();
+    if stem.ends_with(" copy") && !stem.ends_with("- copy") {
+        markers += 1;
+    }
+
+    // "name (1)", "name (2)" ...
+    if let Some(open) = stem.rfind(" (") {
+        let number = &stem[open + 2..];
+        if number.len() > 1 && number.ends_with(')') && number[..number.len() - 1].chars().all(|c| c.is_ascii_digit()) {
+            markers += 1;
+        }
+    }
+
+    markers
+}
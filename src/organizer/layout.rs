@@ -0,0 +1,467 @@
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use anyhow::Result;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Datelike, NaiveDateTime};
+
+use super::{Entry, TimeZone};
+use super::by_name::fold;
+use super::by_size::size_bucket;
+use super::by_type::{OTHERS, file_category};
+use super::exif_data::{ExifData, read_exif};
+
+/// Value used for placeholders that can't be determined for a file
+const UNKNOWN: &str = "Unknown";
+
+/// A destination folder template like `{year}/{month:02}` or `{type}/{ext}`
+#[derive(Debug, Clone)]
+pub struct Layout {
+    parts: Vec<Part>,
+    /// Bytes per KB for `{size}`
+    size_step: u64,
+}
+
+/// A file name template like `{created:%Y%m%d_%H%M%S}_{stem}{ext}` for `--rename`
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+    /// Bytes per KB for `{size}`
+    size_step: u64,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field(Field, Spec),
+}
+
+/// What follows the colon in a placeholder
+#[derive(Debug, Clone)]
+enum Spec {
+    None,
+    /// Zero-pad to this width, e.g. `{month:02}`
+    Width(usize),
+    /// strftime format of a whole date, e.g. `{created:%Y%m%d}`
+    Format(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Date(DateSource, DatePart),
+    /// A whole date, formatted with `Spec::Format`
+    FullDate(DateSource),
+    Stem,
+    Name,
+    Ext,
+    Type,
+    Size,
+    Letter,
+    Parent,
+    Camera,
+    Model,
+}
+
+/// Which date a date placeholder refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateSource {
+    /// The date the mode sorts by, e.g. the creation date for `date`
+    Default,
+    Created,
+    Modified,
+    /// EXIF capture date
+    Taken,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DatePart {
+    Year,
+    Month,
+    Day,
+    Quarter,
+    Week,
+    MonthName,
+    MonthShort,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let parts = parse_parts(template)?;
+
+        // Literals must not lead out of the organized folder
+        let literals: String = parts.iter()
+            .map(|p| match p { Part::Literal(text) => text.as_str(), Part::Field(..) => "x" })
+            .collect();
+        let normalized = literals.replace('\\', "/");
+        if normalized.starts_with('/') || !Path::new(&normalized).components().all(|c| matches!(c, Component::Normal(_))) {
+            anyhow::bail!("Layout '{}' has to be a relative path inside the folder", template);
+        }
+
+        Ok(Layout { parts, size_step: 1024 })
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let parts = parse_parts(template)?;
+        let has_separator = parts.iter().any(|p| matches!(p, Part::Literal(text) if text.contains(['/', '\\'])));
+        if has_separator {
+            anyhow::bail!("Rename template '{}' can only change the file name, use --layout for folders", template);
+        }
+        Ok(NameTemplate { parts, size_step: 1024 })
+    }
+}
+
+fn parse_parts(template: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(Part::Literal(rest[..open].to_string()));
+        }
+        let close = rest[open..].find('}')
+            .ok_or_else(|| anyhow::anyhow!("Unclosed '{{' in template '{}'", template))?;
+        parts.push(parse_placeholder(&rest[open + 1..open + close], template)?);
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Literal(rest.to_string()));
+    }
+
+    Ok(parts)
+}
+
+fn parse_placeholder(placeholder: &str, template: &str) -> Result<Part> {
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, format)) if format.contains('%') => {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                anyhow::bail!("Invalid date format '{}' in template '{}'", format, template);
+            }
+            (name, Spec::Format(format.to_string()))
+        }
+        Some((name, width)) => {
+            let width = width.parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("Invalid width '{}' in template '{}', use e.g. {{month:02}}", width, template))?;
+            (name, Spec::Width(width))
+        }
+        None => (placeholder, Spec::None),
+    };
+
+    let source = |name: &str| match name {
+        "date" => Some(DateSource::Default),
+        "created" => Some(DateSource::Created),
+        "modified" => Some(DateSource::Modified),
+        "taken" => Some(DateSource::Taken),
+        _ => None,
+    };
+
+    let (prefix, part) = match name.split_once('.') {
+        Some((prefix, part)) => (source(prefix), part),
+        None => (Some(DateSource::Default), name),
+    };
+
+    let date_part = match part {
+        "year" => Some(DatePart::Year),
+        "month" => Some(DatePart::Month),
+        "day" => Some(DatePart::Day),
+        "quarter" => Some(DatePart::Quarter),
+        "week" => Some(DatePart::Week),
+        "month_name" => Some(DatePart::MonthName),
+        "month_short" => Some(DatePart::MonthShort),
+        _ => None,
+    };
+
+    let field = match (prefix, date_part) {
+        (Some(source), Some(date_part)) => Some(Field::Date(source, date_part)),
+        _ if name.contains('.') => None,
+        _ => match name {
+            "stem" => Some(Field::Stem),
+            "name" => Some(Field::Name),
+            "ext" => Some(Field::Ext),
+            "type" => Some(Field::Type),
+            "size" => Some(Field::Size),
+            "letter" => Some(Field::Letter),
+            "parent" => Some(Field::Parent),
+            "camera" => Some(Field::Camera),
+            "model" => Some(Field::Model),
+            other => source(other).map(Field::FullDate),
+        },
+    };
+    let Some(field) = field else {
+        anyhow::bail!(
+            "Unknown placeholder '{{{}}}' in template '{}', use year, month, day, quarter, week, month_name, \
+             month_short (optionally prefixed with created., modified. or taken.), date, created, modified, \
+             taken, stem, name, ext, type, size, letter, parent, camera or model",
+            name, template
+        );
+    };
+
+    match (&field, &spec) {
+        (Field::FullDate(_), Spec::Width(_)) => anyhow::bail!("'{{{}}}' needs a date format like {{{}:%Y%m%d}} in template '{}'", name, name, template),
+        (Field::FullDate(_), _) | (_, Spec::None | Spec::Width(_)) => {}
+        (Field::Date(..), Spec::Format(format)) => {
+            let whole = name.split_once('.').map_or("date", |(prefix, _)| prefix);
+            anyhow::bail!(
+                "'{{{}}}' is a number and only takes a width like {{{}:02}}, use {{{}:{}}} to format the date in template '{}'",
+                name, name, whole, format, template
+            )
+        }
+        (_, Spec::Format(_)) => anyhow::bail!("Only dates take a format, '{{{}}}' in template '{}' does not", name, template),
+    }
+
+    Ok(Part::Field(field, spec))
+}
+
+impl Layout {
+    /// Sizes `{size}` in units of `step` bytes per KB, 1000 for SI units
+    pub fn with_units(self, step: u64) -> Self {
+        Layout { size_step: step, ..self }
+    }
+
+    /// The folder `entry` goes to, relative to the organized folder. Unprefixed
+    /// date placeholders use `date`, file times are shown in `timezone`. EXIF
+    /// data is read from the file unless given.
+    pub fn render(&self, entry: &Entry, date: Option<NaiveDateTime>, timezone: TimeZone, exif: Option<&ExifData>) -> PathBuf {
+        let path = render_parts(&self.parts, self.size_step, entry, date, timezone, exif, false);
+        path.split(['/', '\\']).filter(|part| !part.is_empty()).collect()
+    }
+}
+
+impl NameTemplate {
+    /// Like `Layout::with_units`
+    pub fn with_units(self, step: u64) -> Self {
+        NameTemplate { size_step: step, ..self }
+    }
+
+    /// The new file name of `entry`, `{ext}` includes the dot here. Otherwise
+    /// like `Layout::render`.
+    pub fn render(&self, entry: &Entry, date: Option<NaiveDateTime>, timezone: TimeZone, exif: Option<&ExifData>) -> Option<OsString> {
+        let name = render_parts(&self.parts, self.size_step, entry, date, timezone, exif, true);
+        let name = name.trim();
+        (!name.is_empty() && name != "." && name != "..").then(|| OsString::from(name))
+    }
+}
+
+fn render_parts(
+    parts: &[Part],
+    size_step: u64,
+    entry: &Entry,
+    date: Option<NaiveDateTime>,
+    timezone: TimeZone,
+    exif: Option<&ExifData>,
+    in_name: bool,
+) -> String {
+    let needs_exif = exif.is_none() && parts.iter().any(|part| matches!(part,
+        Part::Field(Field::Camera | Field::Model | Field::Date(DateSource::Taken, _) | Field::FullDate(DateSource::Taken), _)));
+    let read = needs_exif.then(|| read_exif(&entry.path)).flatten();
+    let exif = exif.or(read.as_ref());
+
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            Part::Literal(literal) => text.push_str(literal),
+            Part::Field(field, spec) => {
+                let value = field_value(*field, spec, size_step, entry, date, timezone, exif, in_name)
+                    .unwrap_or_else(|| UNKNOWN.to_string());
+                match spec {
+                    Spec::Width(width) => text.push_str(&format!("{:0>width$}", value, width = width)),
+                    _ => text.push_str(&value),
+                }
+            }
+        }
+    }
+    text
+}
+
+#[allow(clippy::too_many_arguments)]
+fn field_value(
+    field: Field,
+    spec: &Spec,
+    size_step: u64,
+    entry: &Entry,
+    date: Option<NaiveDateTime>,
+    timezone: TimeZone,
+    exif: Option<&ExifData>,
+    in_name: bool,
+) -> Option<String> {
+    let extension = entry.path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    let datetime = |source: DateSource| -> Option<NaiveDateTime> {
+        match source {
+            DateSource::Default => date,
+            DateSource::Created => Some(timezone.wall_clock(entry.metadata.created().ok()?)),
+            DateSource::Modified => Some(timezone.wall_clock(entry.metadata.modified().ok()?)),
+            DateSource::Taken => exif?.taken,
+        }
+    };
+
+    let value = match field {
+        Field::FullDate(source) => match spec {
+            Spec::Format(format) => datetime(source)?.format(format).to_string(),
+            _ => datetime(source)?.format("%Y-%m-%d").to_string(),
+        },
+        Field::Date(source, part) => {
+            let datetime = datetime(source)?;
+            match part {
+                DatePart::Year => datetime.year().to_string(),
+                DatePart::Month => datetime.month().to_string(),
+                DatePart::Day => datetime.day().to_string(),
+                DatePart::Quarter => ((datetime.month() - 1) / 3 + 1).to_string(),
+                DatePart::Week => datetime.iso_week().week().to_string(),
+                DatePart::MonthName => datetime.format("%B").to_string(),
+                DatePart::MonthShort => datetime.format("%b").to_string(),
+            }
+        }
+        Field::Stem => entry.path.file_stem()?.to_string_lossy().to_string(),
+        Field::Name => entry.path.file_name()?.to_string_lossy().to_string(),
+        // In a file name the extension keeps its dot and case, so `{stem}{ext}` is the old name
+        Field::Ext if in_name => entry.path.extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default(),
+        Field::Ext => extension.unwrap_or_else(|| "No Extension".to_string()),
+        Field::Type => file_category(&entry.path.file_name()?.to_string_lossy()).unwrap_or(OTHERS).to_string(),
+        Field::Size => size_bucket(entry.metadata.len(), size_step),
+        Field::Letter => {
+            let first = fold(entry.path.file_name()?.to_string_lossy().chars().next()?);
+            if first.is_alphanumeric() { first.to_string() } else { "#".to_string() }
+        }
+        Field::Parent => entry.path.parent()?.file_name()?.to_string_lossy().to_string(),
+        Field::Camera => exif?.make.clone()?,
+        Field::Model => exif?.model.clone()?,
+    };
+
+    if value.is_empty() {
+        return Some(value);
+    }
+    Some(sanitize(&value))
+}
+
+/// Keeps a value from adding folders or using names Windows rejects, which
+/// includes trailing dots and spaces and device names like `CON` or `LPT1`
+pub fn sanitize(value: &str) -> String {
+    let cleaned: String = value.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    match cleaned.trim_start().trim_end_matches(|c: char| c == '.' || c.is_whitespace()) {
+        "" => UNKNOWN.to_string(),
+        trimmed => match device_name(trimmed) {
+            Some(device) => format!("{}_{}", device, &trimmed[device.len()..]),
+            None => trimmed.to_string(),
+        },
+    }
+}
+
+/// The device name `name` starts with, if any. Windows reserves these names,
+/// with or without an extension, in every folder.
+fn device_name(name: &str) -> Option<&str> {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    let upper = stem.to_ascii_uppercase();
+    let reserved = match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        _ => ["COM", "LPT"].iter().any(|prefix| {
+            upper.strip_prefix(prefix).is_some_and(|digit| matches!(digit, "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "¹" | "²" | "³"))
+        }),
+    };
+    reserved.then_some(stem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn error(template: &str) -> String {
+        template.parse::<Layout>().unwrap_err().to_string()
+    }
+
+    /// An entry for a file in a folder of its own, modified at `2024-12-31 23:30 UTC`
+    fn entry(name: &str) -> Entry {
+        let folder = std::env::temp_dir().join(format!("organizer-layout-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let path = folder.join(name);
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1_735_687_800)).unwrap();
+        Entry { metadata: std::fs::metadata(&path).unwrap(), relative: PathBuf::from(name), path }
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(10, 11, 12)
+    }
+
+    #[test]
+    fn malformed_templates() {
+        assert!(error("{year").contains("Unclosed"));
+        assert!(error("{bogus}").contains("Unknown placeholder '{bogus}'"));
+        assert!(error("{taken.bogus}").contains("Unknown placeholder"));
+        assert!(error("{month:ab}").contains("Invalid width 'ab'"));
+        assert!(error("{created:%Q}").contains("Invalid date format"));
+        assert!(error("{stem:%Y}").contains("Only dates take a format"));
+        assert!(error("{created:04}").contains("needs a date format like {created:%Y%m%d}"));
+        assert!(error("../{year}").contains("relative path"));
+        assert!(error("/{year}").contains("relative path"));
+        assert!("{year}/{stem}".parse::<NameTemplate>().unwrap_err().to_string().contains("use --layout"));
+    }
+
+    #[test]
+    fn date_parts_point_to_whole_date_formats() {
+        assert!(error("{year:%Y}").contains("use {date:%Y}"));
+        assert!(error("{modified.month:%m}").contains("use {modified:%m}"));
+    }
+
+    #[test]
+    fn layouts_render_folders() {
+        let entry = entry("report.PDF");
+        let render = |template: &str| template.parse::<Layout>().unwrap().render(&entry, date(2024, 3, 5), TimeZone::Utc, None);
+
+        assert_eq!(render("{year}/{month:02}"), Path::new("2024/03"));
+        assert_eq!(render("{type}/{ext}"), Path::new("Documents/pdf"));
+        assert_eq!(render("{year}/Q{quarter}/{month_name}"), Path::new("2024/Q1/March"));
+        assert_eq!(render("{date:%Y-%m-%d}"), Path::new("2024-03-05"));
+        assert_eq!(render("{camera}"), Path::new(UNKNOWN));
+        assert_eq!(render("a//{letter}/"), Path::new("a/R"));
+    }
+
+    #[test]
+    fn rename_templates_keep_the_extension() {
+        let entry = entry("IMG 1.jpg");
+        let render = |template: &str| template.parse::<NameTemplate>().unwrap().render(&entry, date(2024, 3, 5), TimeZone::Utc, None);
+
+        assert_eq!(render("{date:%Y%m%d_%H%M%S}_{stem}{ext}").unwrap(), "20240305_101112_IMG 1.jpg");
+        assert_eq!(render("{stem}{ext}").unwrap(), "IMG 1.jpg");
+        assert_eq!(render("  "), None);
+    }
+
+    #[test]
+    fn file_times_use_the_time_zone() {
+        let entry = entry("notes.txt");
+        let template: NameTemplate = "{modified:%Y-%m-%d %H.%M}".parse().unwrap();
+        let render = |timezone: &str| template.render(&entry, None, timezone.parse().unwrap(), None).unwrap();
+
+        assert_eq!(render("utc"), "2024-12-31 23.30");
+        assert_eq!(render("America/New_York"), "2024-12-31 18.30");
+        assert_eq!(render("Asia/Tokyo"), "2025-01-01 08.30");
+    }
+
+    #[test]
+    fn sanitize_avoids_names_windows_rejects() {
+        assert_eq!(sanitize("AC/DC"), "AC_DC");
+        assert_eq!(sanitize("What?: Live"), "What__ Live");
+        assert_eq!(sanitize("  Vol. 2. . "), "Vol. 2");
+        assert_eq!(sanitize("..."), UNKNOWN);
+        assert_eq!(sanitize(""), UNKNOWN);
+        assert_eq!(sanitize("Con"), "Con_");
+        assert_eq!(sanitize("Aux."), "Aux_");
+        assert_eq!(sanitize("nul.txt"), "nul_.txt");
+        assert_eq!(sanitize("prn .tar.gz"), "prn_ .tar.gz");
+        assert_eq!(sanitize("COM1"), "COM1_");
+        assert_eq!(sanitize("lpt9"), "lpt9_");
+        assert_eq!(sanitize("COM10"), "COM10");
+        assert_eq!(sanitize("Console"), "Console");
+        assert_eq!(sanitize("Auxiliary"), "Auxiliary");
+    }
+}
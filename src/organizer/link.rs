@@ -0,0 +1,141 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// How a redundant copy is replaced by a reference to the kept file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    Hard,
+    Symlink,
+    /// Copy-on-write clone, only on filesystems like ReFS, Btrfs or APFS
+    Reflink,
+}
+
+impl FromStr for LinkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "hard" => Ok(LinkKind::Hard),
+            "symlink" => Ok(LinkKind::Symlink),
+            "reflink" => Ok(LinkKind::Reflink),
+            _ => Err(anyhow::anyhow!("Invalid link type '{}', use hard, symlink or reflink", value)),
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkKind::Hard => write!(f, "hard link"),
+            LinkKind::Symlink => write!(f, "symbolic link"),
+            LinkKind::Reflink => write!(f, "reflink"),
+        }
+    }
+}
+
+/// A duplicate replaced in place by a link to the file that was kept
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileLink {
+    pub path: PathBuf,
+    pub target: PathBuf,
+    pub kind: LinkKind,
+    /// SHA-256 of the content both files shared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Replaces `link.path` with a link to `link.target`. The link is created
+/// next to the file first, so on failure the duplicate is left untouched.
+pub async fn replace_with_link(link: &FileLink) -> io::Result<()> {
+    let temp = temp_path(&link.path);
+    let target = link.target.clone();
+    let created = temp.clone();
+
+    let result = match link.kind {
+        LinkKind::Hard => fs::hard_link(&target, &temp).await,
+        // A relative target would be read from the link's own folder
+        LinkKind::Symlink => match fs::canonicalize(&target).await {
+            Ok(absolute) => symlink_file(&absolute, &temp).await,
+            Err(e) => Err(e),
+        },
+        LinkKind::Reflink => tokio::task::spawn_blocking(move || reflink_copy::reflink(&target, &created))
+            .await
+            .map_err(io::Error::other)?,
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp).await;
+        return Err(e);
+    }
+
+    replace(&temp, &link.path).await
+}
+
+/// Turns a link back into an independent copy of its target
+pub async fn replace_with_copy(link: &FileLink) -> io::Result<()> {
+    let temp = temp_path(&link.path);
+    if let Err(e) = fs::copy(&link.target, &temp).await {
+        let _ = fs::remove_file(&temp).await;
+        return Err(e);
+    }
+    replace(&temp, &link.path).await
+}
+
+async fn replace(temp: &Path, path: &Path) -> io::Result<()> {
+    let result = fs::rename(temp, path).await;
+    if result.is_err() {
+        let _ = fs::remove_file(temp).await;
+    }
+    result
+}
+
+/// Whether both paths are hard links to the same file
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (file_id(a), file_id(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(windows)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
+
+    let file = std::fs::File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    let index = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+    Some((info.dwVolumeSerialNumber as u64, index))
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.organize_link", name))
+}
+
+#[cfg(windows)]
+async fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    fs::symlink_file(target, link).await
+}
+
+#[cfg(not(windows))]
+async fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    fs::symlink(target, link).await
+}
@@ -3,84 +3,341 @@ pub mod by_date;
 pub mod by_name;
 pub(crate) mod flatten;
 pub(crate) mod remove_duplicates;
-pub(crate) mod by_modified_date;
 pub(crate) mod by_size;
+pub(crate) mod plan;
+pub(crate) mod journal;
+pub(crate) mod history;
+pub(crate) mod quarantine;
+pub(crate) mod keep_policy;
+pub(crate) mod hash_cache;
+pub(crate) mod link;
+pub(crate) mod report;
+pub(crate) mod near_duplicates;
+pub(crate) mod rules;
+pub(crate) mod layout;
+pub(crate) mod exif_data;
+pub(crate) mod photo_date;
+pub(crate) mod music;
+pub(crate) mod sniff;
+pub(crate) mod walk;
+pub(crate) mod hidden;
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use chrono::{DateTime, Utc};
+use std::str::FromStr;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
 
+use crate::menu;
+
+pub use by_date::TimeZone;
+pub use exif_data::ExifData;
+pub use history::{History, log_action};
+pub use layout::{Layout, NameTemplate};
+pub use plan::{Plan, execute, move_file};
+pub use walk::{Walk, collect_entries, walk_tree};
+
+/// An executed plan, as recorded in the action log
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct OrganizeAction {
+    /// Assigned when the action is logged
+    #[serde(default)]
+    pub id: u64,
     pub timestamp: DateTime<Utc>,
-    pub action_type: String,
-    pub moves: Vec<FileMove>,
+    #[serde(flatten)]
+    pub plan: Plan,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileMove {
     pub from: PathBuf,
     pub to: PathBuf,
+    /// SHA-256 of the content, recorded for quarantined duplicates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// The file that was kept when this one was set aside as a duplicate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<PathBuf>,
+    /// Why the file went where it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// A file found while walking the target folder
+pub struct Entry {
+    pub path: PathBuf,
+    /// Path relative to the folder being organized
+    pub relative: PathBuf,
+    pub metadata: std::fs::Metadata,
+}
+
+/// Describes an extra command-line option understood by a mode
+pub struct ModeOption {
+    pub name: &'static str,
+    pub short: Option<char>,
+    pub value_name: &'static str,
+    pub help: &'static str,
+    pub default: Option<&'static str>,
+    /// Takes no value, set to "true" when given
+    pub flag: bool,
+}
+
+/// Option values passed on the command line, keyed by `ModeOption::name`
+#[derive(Debug, Default, Clone)]
+pub struct ModeOptions {
+    values: HashMap<String, String>,
+}
+
+impl ModeOptions {
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.values.insert(name.to_string(), value.into());
+    }
+
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.values.get(name).and_then(|v| v.parse().ok())
+    }
+}
+
+/// An organization mode. Most modes only implement `classify`, the shared
+/// `plan_entries` driver takes care of walking the folder and resolving conflicts.
+#[async_trait]
+pub trait Organizer: Send + Sync {
+    /// Value accepted by `--mode`
+    fn name(&self) -> &'static str;
+
+    /// Label used for the context menu entry and `--help`, from `menu.txt`
+    fn description(&self) -> &'static str {
+        menu::label(self.name()).unwrap_or(self.name())
+    }
+
+    fn options(&self) -> &'static [ModeOption] {
+        &[]
+    }
+
+    /// Whether the mode always works on the whole folder tree, regardless of
+    /// `--recursive`
+    fn recursive(&self) -> bool {
+        false
+    }
+
+    /// Name recorded in the action log, used by undo
+    fn action_type(&self, _options: &ModeOptions) -> String {
+        format!("by_{}", self.name())
+    }
+
+    /// Wall clock date the unprefixed date placeholders of `--layout` and
+    /// `--rename` refer to
+    fn layout_date(&self, entry: &Entry, options: &ModeOptions) -> Option<NaiveDateTime> {
+        Some(TimeZone::from_options(options).ok()?.wall_clock(entry.metadata.modified().ok()?))
+    }
+
+    /// Returns the destination of a file relative to the organized folder,
+    /// or `None` to leave the file where it is
+    fn classify(&self, _entry: &Entry, _options: &ModeOptions) -> Option<PathBuf> {
+        None
+    }
+
+    /// Works out what the mode would do without touching the disk
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        plan_entries(self, folder, options).await
+    }
+
+    /// Describes what the mode found without changing anything, for modes
+    /// that declare a `report` option
+    async fn report(&self, _folder: &Path, _format: &str, _options: &ModeOptions) -> Result<String> {
+        anyhow::bail!("{} has no report", self.name())
+    }
+}
+
+static ORGANIZERS: &[&dyn Organizer] = &[
+    &by_type::ByType,
+    &by_date::BY_CREATED,
+    &by_date::BY_MODIFIED,
+    &photo_date::ByPhotoDate,
+    &music::ByMusic,
+    &by_size::BySize,
+    &by_name::ByName,
+    &flatten::Flatten,
+    &remove_duplicates::RemoveDuplicates,
+    &near_duplicates::NearDuplicates,
+    &rules::ByRules,
+];
+
+/// All available modes, in context menu order
+pub fn registry() -> &'static [&'static dyn Organizer] {
+    ORGANIZERS
 }
 
-pub fn get_log_path(folder: &std::path::Path) -> PathBuf {
-    folder.join(".organize_log.json")
+pub fn find_organizer(name: &str) -> Option<&'static dyn Organizer> {
+    ORGANIZERS.iter().copied().find(|o| o.name() == name)
 }
 
-pub async fn log_action(folder: &std::path::Path, action: OrganizeAction) -> anyhow::Result<()> {
-    let log_path = get_log_path(folder);
-    let mut actions: Vec<OrganizeAction> = if log_path.exists() {
-        let content = tokio::fs::read_to_string(&log_path).await?;
-        serde_json::from_str(&content).unwrap_or_default()
+/// Walks `folder` and plans a move to the location chosen by `classify` for every file.
+/// A `--layout` replaces the folders `classify` picked, the file name stays.
+pub async fn plan_entries<O: Organizer + ?Sized>(organizer: &O, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+    plan_classified(organizer, folder, options, |entry| organizer.classify(entry, options)).await
+}
+
+/// Like `plan_entries`, for modes that classify with state prepared in `plan`.
+/// A `--layout` still replaces the folders `classify` picked.
+pub async fn plan_classified<O: Organizer + ?Sized>(
+    organizer: &O,
+    folder: &Path,
+    options: &ModeOptions,
+    classify: impl Fn(&Entry) -> Option<PathBuf>,
+) -> Result<Plan> {
+    plan_detailed(organizer, folder, options, classify, |entry| (organizer.layout_date(entry, options), None)).await
+}
+
+/// The date unprefixed date placeholders refer to and the EXIF data of a
+/// file, for modes that read them in `plan` anyway
+pub type TemplateDetails<'a> = (Option<NaiveDateTime>, Option<&'a ExifData>);
+
+/// Like `plan_classified`, with the templates filled from `details` instead
+/// of `layout_date` and another read of the EXIF data
+pub async fn plan_detailed<'a, O: Organizer + ?Sized>(
+    organizer: &O,
+    folder: &Path,
+    options: &ModeOptions,
+    classify: impl Fn(&Entry) -> Option<PathBuf>,
+    details: impl Fn(&Entry) -> TemplateDetails<'a>,
+) -> Result<Plan> {
+    let layout = options.get::<String>("layout").map(|l| l.parse::<Layout>()).transpose()?
+        .map(|layout| layout.with_units(size_units(options)));
+    let timezone = TimeZone::from_options(options)?;
+
+    plan_with_details(organizer, folder, options, |entry| {
+        let destination = classify(entry)?;
+        match &layout {
+            Some(layout) => {
+                let (date, exif) = details(entry);
+                Some(layout.render(entry, date, timezone, exif).join(destination.file_name()?))
+            }
+            None => Some(destination),
+        }
+    }, &details).await
+}
+
+/// Plans a move to the location chosen by `classify` for every file, for
+/// modes that pick whole destinations themselves. Applies `--rename` to every
+/// destination.
+pub async fn plan_with<O: Organizer + ?Sized>(
+    organizer: &O,
+    folder: &Path,
+    options: &ModeOptions,
+    classify: impl Fn(&Entry) -> Option<PathBuf>,
+) -> Result<Plan> {
+    plan_with_details(organizer, folder, options, classify, |entry| (organizer.layout_date(entry, options), None)).await
+}
+
+async fn plan_with_details<'a, O: Organizer + ?Sized>(
+    organizer: &O,
+    folder: &Path,
+    options: &ModeOptions,
+    classify: impl Fn(&Entry) -> Option<PathBuf>,
+    details: impl Fn(&Entry) -> TemplateDetails<'a>,
+) -> Result<Plan> {
+    let rename = options.get::<String>("rename").map(|r| r.parse::<NameTemplate>()).transpose()?
+        .map(|rename| rename.with_units(size_units(options)));
+    let timezone = TimeZone::from_options(options)?;
+    let walk = Walk::from_options(organizer, folder, options).await?;
+    let entries = collect_entries(folder, &walk).await?;
+    let mut plan = Plan::new(organizer.action_type(options));
+
+    for entry in entries {
+        let Some(mut destination) = classify(&entry) else {
+            continue;
+        };
+        if let Some(rename) = &rename {
+            let (date, exif) = details(&entry);
+            if let Some(name) = rename.render(&entry, date, timezone, exif) {
+                destination.set_file_name(name);
+            }
+        }
+
+        // With --recursive every folder is organized on its own
+        let base = match entry.path.parent() {
+            Some(parent) if walk.in_place() => parent,
+            _ => folder,
+        };
+        let new_path = base.join(destination);
+        if new_path == entry.path {
+            continue;
+        }
+        plan.add_move(entry.path, new_path)?;
+    }
+
+    Ok(plan)
+}
+
+/// Bytes per KB of `{size}`, following `--units` of the size mode
+fn size_units(options: &ModeOptions) -> u64 {
+    by_size::units(options).unwrap_or(1024)
+}
+
+/// `name` in the organizer's config directory, `%APPDATA%\organizer` on
+/// Windows and `~/.config/organizer` elsewhere
+pub fn user_config_file(name: &str) -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
     } else {
-        Vec::new()
+        std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
     };
+    Some(config_dir?.join("organizer").join(name))
+}
 
-    actions.push(action);
+/// Replaces `path` with `contents` through a temporary file in the same folder,
+/// so a crash leaves either the old or the new file and never a torn one
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
 
-    // Keep only last 10 actions to prevent log from growing too large
-    if actions.len() > 10 {
-        actions = actions.clone().into_iter().skip(actions.len() - 10).collect();
-    }
+    let mut file = tokio::fs::File::create(&temp).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, contents).await?;
+    file.sync_all().await?;
+    drop(file);
 
-    let content = serde_json::to_string_pretty(&actions)?;
-    tokio::fs::write(&log_path, content).await?;
+    if let Err(e) = tokio::fs::rename(&temp, path).await {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
 
-    // Hide the log file
+/// Sets the hidden attribute on the organizer's own bookkeeping files
+pub fn hide_file(path: &Path) {
     #[cfg(windows)]
     {
         use std::ffi::CString;
         use winapi::um::fileapi::SetFileAttributesA;
         use winapi::um::winnt::FILE_ATTRIBUTE_HIDDEN;
 
-        if let Ok(c_path) = CString::new(log_path.to_string_lossy().as_bytes()) {
+        if let Ok(c_path) = CString::new(path.to_string_lossy().as_bytes()) {
             unsafe {
                 SetFileAttributesA(c_path.as_ptr(), FILE_ATTRIBUTE_HIDDEN);
             }
         }
     }
-
-    Ok(())
+    #[cfg(not(windows))]
+    let _ = path;
 }
 
-pub async fn handle_name_conflict(path: &Path) -> anyhow::Result<PathBuf> {
-    if !path.exists() {
-        return Ok(path.to_path_buf());
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let parent = path.parent().unwrap();
-    let filename = path.file_stem().unwrap().to_string_lossy();
-    let extension = path.extension()
-        .map(|e| format!(".{}", e.to_string_lossy()))
-        .unwrap_or_default();
-
-    for i in 1..1000 {
-        let new_name = format!("{} ({}){}", filename, i, extension);
-        let new_path = parent.join(new_name);
-        if !new_path.exists() {
-            return Ok(new_path);
+    #[test]
+    fn menu_lists_every_mode() {
+        let modes: Vec<&str> = menu::entries().map(|entry| entry.mode).collect();
+        for organizer in registry() {
+            assert!(modes.contains(&organizer.name()), "{} is missing from menu.txt", organizer.name());
+        }
+        for mode in modes {
+            assert!(find_organizer(mode).is_some() || mode == "undo", "menu.txt lists unknown mode {}", mode);
         }
     }
-
-    Err(anyhow::anyhow!("Could not resolve name conflict for: {}", path.display()))
-}
\ No newline at end of file
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use lofty::config::ParseOptions;
+use lofty::prelude::*;
+use lofty::probe::Probe;
+
+use super::layout::sanitize;
+use super::{Entry, ModeOptions, Organizer};
+
+/// Audio formats whose tags can be read
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "ogg", "oga", "opus", "spx", "m4a", "m4b", "aac", "wav", "aif", "aiff", "ape", "wv", "mpc",
+];
+/// Folder for audio files without usable tags
+const UNKNOWN_DIR: &str = "Unknown";
+
+/// The tags a music file is filed by
+#[derive(Debug, Default)]
+struct TrackTags {
+    artist: Option<String>,
+    album: Option<String>,
+    title: Option<String>,
+    track: Option<u32>,
+}
+
+/// Reads ID3v2, Vorbis comment, MP4 and the other tag formats of `path`,
+/// preferring the format native to the container
+fn read_tags(path: &Path) -> Option<TrackTags> {
+    let file = Probe::open(path).ok()?
+        .options(ParseOptions::new().read_properties(false))
+        .read().ok()?;
+    let tag = file.primary_tag().or_else(|| file.first_tag())?;
+
+    let text = |value: Option<&str>| value.map(str::trim).filter(|v| !v.is_empty()).map(str::to_string);
+    Some(TrackTags {
+        // The album artist keeps compilations in one folder
+        artist: text(tag.get_string(&ItemKey::AlbumArtist)).or_else(|| text(tag.artist().as_deref())),
+        album: text(tag.album().as_deref()),
+        title: text(tag.title().as_deref()),
+        track: tag.track().filter(|&track| track > 0),
+    })
+}
+
+pub struct ByMusic;
+
+#[async_trait]
+impl Organizer for ByMusic {
+    fn name(&self) -> &'static str {
+        "music"
+    }
+
+    /// `Artist/Album/NN - Title.ext`, files without a title go to `Unknown`
+    /// under their own name
+    fn classify(&self, entry: &Entry, _options: &ModeOptions) -> Option<PathBuf> {
+        let file_name = entry.path.file_name()?;
+        let extension = entry.path.extension()?.to_string_lossy();
+        if !AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            return None;
+        }
+
+        let tags = read_tags(&entry.path).unwrap_or_default();
+        let Some(title) = tags.title else {
+            return Some(Path::new(UNKNOWN_DIR).join(file_name));
+        };
+
+        let artist = tags.artist.as_deref().map(sanitize).unwrap_or_else(|| "Unknown Artist".to_string());
+        let album = tags.album.as_deref().map(sanitize).unwrap_or_else(|| "Unknown Album".to_string());
+        let name = match tags.track {
+            Some(track) => format!("{:02} - {}.{}", track, sanitize(&title), extension),
+            None => format!("{}.{}", sanitize(&title), extension),
+        };
+
+        Some(Path::new(&artist).join(album).join(name))
+    }
+}
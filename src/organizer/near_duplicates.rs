@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use anyhow::Result;
+use async_trait::async_trait;
+use image::imageops::FilterType;
+use image::{GrayImage, ImageReader};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use super::report::{self, ReportFormat};
+use super::{ModeOption, ModeOptions, Organizer, Plan, Walk, collect_entries};
+
+static OPTIONS: &[ModeOption] = &[
+    ModeOption {
+        name: "algorithm",
+        short: None,
+        value_name: "HASH",
+        help: "Perceptual hash for near_duplicates: ahash, dhash or phash",
+        default: Some("dhash"),
+        flag: false,
+    },
+    ModeOption {
+        name: "distance",
+        short: None,
+        value_name: "BITS",
+        help: "Largest Hamming distance (0-64) between two images still counted as near duplicates",
+        default: Some("10"),
+        flag: false,
+    },
+    ModeOption {
+        name: "report",
+        short: None,
+        value_name: "FORMAT",
+        help: "Only print a json, csv or html report of the duplicate groups, nothing is moved",
+        default: None,
+        flag: false,
+    },
+];
+
+/// Extensions of the image formats that can be decoded
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff"];
+/// Directory the lower resolution copies are moved into for review
+const REVIEW_DIR: &str = "Near Duplicates";
+const MAX_PARALLEL_DECODES: usize = 4;
+
+/// How the 64-bit fingerprint of an image is computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Pixels brighter than the mean of an 8x8 thumbnail
+    Average,
+    /// Brightness gradient between neighbouring pixels of a 9x8 thumbnail
+    Difference,
+    /// Low frequencies of the DCT of a 32x32 thumbnail
+    Perceptual,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "ahash" => Ok(HashAlgorithm::Average),
+            "dhash" => Ok(HashAlgorithm::Difference),
+            "phash" => Ok(HashAlgorithm::Perceptual),
+            _ => Err(anyhow::anyhow!("Invalid hash algorithm '{}', use ahash, dhash or phash", value)),
+        }
+    }
+}
+
+/// A decoded image and its fingerprint
+pub(crate) struct ImageInfo {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl ImageInfo {
+    fn pixels(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+/// Images that look alike, the suggested keeper first
+pub(crate) struct NearDuplicateGroup {
+    pub images: Vec<ImageInfo>,
+}
+
+impl NearDuplicateGroup {
+    /// Hamming distance of the image at `index` to the keeper
+    pub fn distance(&self, index: usize) -> u32 {
+        (self.images[0].hash ^ self.images[index].hash).count_ones()
+    }
+}
+
+pub struct NearDuplicates;
+
+#[async_trait]
+impl Organizer for NearDuplicates {
+    fn name(&self) -> &'static str {
+        "near_duplicates"
+    }
+
+    fn recursive(&self) -> bool {
+        true
+    }
+
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
+    }
+
+    fn action_type(&self, _options: &ModeOptions) -> String {
+        "near_duplicates".to_string()
+    }
+
+    /// Leaves the highest resolution image of every group in place and moves
+    /// the others into a review folder named after it
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        let mut plan = Plan::new(self.action_type(options));
+
+        for (index, group) in find_near_duplicates(folder, options).await?.iter().enumerate() {
+            let keeper = &group.images[0];
+            let keeper_name = keeper.path.file_name().unwrap().to_string_lossy();
+            let review_dir = folder.join(REVIEW_DIR).join(format!("{:03} {}", index + 1, keeper_name));
+
+            for (i, image) in group.images.iter().enumerate().skip(1) {
+                let file_move = plan.add_move(image.path.clone(), review_dir.join(image.path.file_name().unwrap()))?;
+                file_move.duplicate_of = Some(keeper.path.clone());
+                file_move.note = Some(format!(
+                    "{}x{} against {}x{}, distance {}",
+                    image.width, image.height, keeper.width, keeper.height, group.distance(i),
+                ));
+            }
+        }
+
+        Ok(plan)
+    }
+
+    async fn report(&self, folder: &Path, format: &str, options: &ModeOptions) -> Result<String> {
+        let format: ReportFormat = format.parse()?;
+        let groups = find_near_duplicates(folder, options).await?;
+        report::render_near_duplicates(folder, &groups, format)
+    }
+}
+
+/// Groups the images under `folder` whose fingerprints are within the
+/// configured distance of each other, directly or through other images
+pub(crate) async fn find_near_duplicates(folder: &Path, options: &ModeOptions) -> Result<Vec<NearDuplicateGroup>> {
+    let algorithm: HashAlgorithm = options.get::<String>("algorithm").unwrap_or_default().parse()?;
+    let max_distance = match options.get::<String>("distance") {
+        Some(distance) => distance.parse::<u32>().ok().filter(|bits| *bits <= 64)
+            .ok_or_else(|| anyhow::anyhow!("Invalid --distance '{}', expected a number of bits from 0 to 64", distance))?,
+        None => 10,
+    };
+
+    let review_dir = folder.join(REVIEW_DIR);
+    let walk = Walk::from_options(&NearDuplicates, folder, options).await?;
+    let paths: Vec<PathBuf> = collect_entries(folder, &walk).await?
+        .into_iter()
+        .filter(|entry| is_image(&entry.path) && !entry.path.starts_with(&review_dir))
+        .map(|entry| entry.path)
+        .collect();
+
+    let images = hash_images(paths, algorithm).await;
+
+    // Union-find over every pair close enough to each other
+    let mut parent: Vec<usize> = (0..images.len()).collect();
+    for a in 0..images.len() {
+        for b in a + 1..images.len() {
+            if (images[a].hash ^ images[b].hash).count_ones() <= max_distance {
+                let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+                parent[ra] = rb;
+            }
+        }
+    }
+
+    let mut clusters: Vec<Vec<ImageInfo>> = (0..images.len()).map(|_| Vec::new()).collect();
+    for (index, image) in images.into_iter().enumerate() {
+        let r = root(&mut parent, index);
+        clusters[r].push(image);
+    }
+
+    let mut groups: Vec<NearDuplicateGroup> = clusters.into_iter()
+        .filter(|images| images.len() > 1)
+        .map(|mut images| {
+            // Highest resolution first, then the largest file as the least compressed
+            images.sort_by(|a, b| b.pixels().cmp(&a.pixels())
+                .then_with(|| b.size.cmp(&a.size))
+                .then_with(|| a.path.cmp(&b.path)));
+            NearDuplicateGroup { images }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.images[0].path.cmp(&b.images[0].path));
+
+    Ok(groups)
+}
+
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// Decodes and fingerprints the images on a few blocking threads.
+/// Images that can't be decoded are reported and left out.
+async fn hash_images(paths: Vec<PathBuf>, algorithm: HashAlgorithm) -> Vec<ImageInfo> {
+    let permits = Arc::new(Semaphore::new(MAX_PARALLEL_DECODES));
+    let mut tasks = JoinSet::new();
+
+    for path in paths {
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let decoded = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || image_info(path, algorithm)
+            }).await;
+            (path, decoded)
+        });
+    }
+
+    let mut images = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((_, Ok(Ok(image)))) => images.push(image),
+            Ok((path, Ok(Err(e)))) => eprintln!("Skipping {}: {}", path.display(), e),
+            _ => {}
+        }
+    }
+    images.sort_by(|a, b| a.path.cmp(&b.path));
+    images
+}
+
+fn image_info(path: PathBuf, algorithm: HashAlgorithm) -> Result<ImageInfo> {
+    let size = std::fs::metadata(&path)?.len();
+    let image = ImageReader::open(&path)?.with_guessed_format()?.decode()?;
+    let (width, height) = (image.width(), image.height());
+
+    let hash = match algorithm {
+        HashAlgorithm::Average => average_hash(&image.resize_exact(8, 8, FilterType::Triangle).to_luma8()),
+        HashAlgorithm::Difference => difference_hash(&image.resize_exact(9, 8, FilterType::Triangle).to_luma8()),
+        HashAlgorithm::Perceptual => perceptual_hash(&image.resize_exact(32, 32, FilterType::Triangle).to_luma8()),
+    };
+
+    Ok(ImageInfo { path, width, height, size, hash })
+}
+
+fn average_hash(thumbnail: &GrayImage) -> u64 {
+    let pixels: Vec<u32> = thumbnail.pixels().map(|p| p.0[0] as u32).collect();
+    let mean = pixels.iter().sum::<u32>() / pixels.len() as u32;
+    bits(pixels.iter().map(|&p| p > mean))
+}
+
+fn difference_hash(thumbnail: &GrayImage) -> u64 {
+    bits((0..8).flat_map(|y| (0..8).map(move |x| (x, y)))
+        .map(|(x, y)| thumbnail.get_pixel(x, y).0[0] < thumbnail.get_pixel(x + 1, y).0[0]))
+}
+
+fn perceptual_hash(thumbnail: &GrayImage) -> u64 {
+    const N: usize = 32;
+    let pixel = |x: usize, y: usize| thumbnail.get_pixel(x as u32, y as u32).0[0] as f64;
+    let cosine = |k: usize, n: usize| ((2 * n + 1) as f64 * k as f64 * std::f64::consts::PI / (2 * N) as f64).cos();
+
+    // Only the top-left 8x8 coefficients of the 2D DCT are needed
+    let mut coefficients = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += pixel(x, y) * cosine(u, x) * cosine(v, y);
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    // The DC term only reflects overall brightness
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    bits(coefficients.iter().map(|&c| c > median))
+}
+
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
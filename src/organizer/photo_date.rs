@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+use super::exif_data::read_exif;
+use super::{Entry, ExifData, ModeOptions, Organizer, Plan, Walk, collect_entries, plan_detailed};
+
+/// Where the date of a photo came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhotoDateSource {
+    /// EXIF DateTimeOriginal or one of its fallbacks
+    Exif,
+    /// A timestamp in the file name, e.g. `IMG_20240315_101112.jpg`
+    FileName,
+    /// The file's modification time, which copying usually preserves
+    Modified,
+}
+
+impl PhotoDateSource {
+    fn describe(self) -> &'static str {
+        match self {
+            PhotoDateSource::Exif => "date from EXIF",
+            PhotoDateSource::FileName => "date from file name",
+            PhotoDateSource::Modified => "date from modification time",
+        }
+    }
+}
+
+/// Pictures and camera RAW files the mode picks up, other files stay where they are
+const PHOTO_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp", "heic", "heif", "avif",
+    "dng", "cr2", "cr3", "nef", "nrw", "arw", "srf", "sr2", "orf", "rw2", "pef", "raf", "srw",
+];
+
+/// RAW formats whose EXIF can't be read, they are dated by file name or
+/// modification time
+const NO_EXIF_EXTENSIONS: &[&str] = &["cr3", "orf", "rw2", "raf"];
+
+/// A capture date and where it was found
+type PhotoDate = (NaiveDateTime, PhotoDateSource);
+
+/// What the mode reads from a picture, once per file. The folders, the
+/// templates and the plan notes all use it, and reading EXIF is the slow part.
+struct Photo {
+    date: NaiveDateTime,
+    source: PhotoDateSource,
+    exif: ExifData,
+}
+
+/// Dates like `20240315`, `2024-03-15` or `2024_03_15`, optionally followed by a
+/// time like `_101112` or ` 10.11.12`, as phones, cameras and messengers write them
+static FILE_NAME_DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|\D)((?:19|20)\d{2})[-_.]?(\d{2})[-_.]?(\d{2})(?:[-_. T]?(\d{2})[-_.:]?(\d{2})[-_.:]?(\d{2}))?(?:\D|$)").unwrap()
+});
+
+/// When the picture at `path` was taken, with the source the date was read
+/// from: the EXIF capture time `taken`, a date in the file name or the
+/// modification time. EXIF and file name dates are camera local time, the
+/// modification time is converted to local time.
+pub fn photo_date(path: &Path, taken: Option<NaiveDateTime>, modified: Option<SystemTime>) -> Option<PhotoDate> {
+    if let Some(taken) = taken {
+        return Some((taken, PhotoDateSource::Exif));
+    }
+
+    let name = path.file_stem()?.to_string_lossy();
+    if let Some(date) = date_from_file_name(&name) {
+        return Some((date, PhotoDateSource::FileName));
+    }
+
+    Some((DateTime::<Local>::from(modified?).naive_local(), PhotoDateSource::Modified))
+}
+
+fn exif_readable(path: &Path) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    !NO_EXIF_EXTENSIONS.contains(&extension.as_str())
+}
+
+/// The EXIF data and date of a picture, `None` for other files
+fn read_photo(entry: &Entry) -> Option<Photo> {
+    let extension = entry.path.extension()?.to_string_lossy().to_lowercase();
+    if !PHOTO_EXTENSIONS.contains(&extension.as_str()) {
+        return None;
+    }
+
+    let exif = exif_readable(&entry.path).then(|| read_exif(&entry.path)).flatten().unwrap_or_default();
+    let (date, source) = photo_date(&entry.path, exif.taken, entry.metadata.modified().ok())?;
+    Some(Photo { date, source, exif })
+}
+
+/// The first plausible date in a file name
+pub fn date_from_file_name(name: &str) -> Option<NaiveDateTime> {
+    FILE_NAME_DATE.captures_iter(name).find_map(|captures| {
+        let number = |i: usize| captures.get(i).and_then(|m| m.as_str().parse::<u32>().ok());
+        let date = NaiveDate::from_ymd_opt(number(1)? as i32, number(2)?, number(3)?)?;
+        // Digits after the date that are no time of day still leave the date
+        match (number(4), number(5), number(6)) {
+            (Some(hour), Some(minute), Some(second)) => date.and_hms_opt(hour, minute, second).or(date.and_hms_opt(0, 0, 0)),
+            _ => date.and_hms_opt(0, 0, 0),
+        }
+    })
+}
+
+pub struct ByPhotoDate;
+
+#[async_trait]
+impl Organizer for ByPhotoDate {
+    fn name(&self) -> &'static str {
+        "photo_date"
+    }
+
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        let walk = Walk::from_options(self, folder, options).await?;
+        let photos: HashMap<PathBuf, Photo> = collect_entries(folder, &walk).await?.iter()
+            .filter_map(|entry| Some((entry.path.clone(), read_photo(entry)?)))
+            .collect();
+
+        // Templates show the capture date, the camera's wall clock
+        let mut plan = plan_detailed(self, folder, options,
+            |entry| {
+                let date = photos.get(&entry.path)?.date;
+                let year = date.format("%Y").to_string();
+                let month = date.format("%Y-%m").to_string();
+                Some(Path::new(&year).join(month).join(entry.path.file_name()?))
+            },
+            |entry| match photos.get(&entry.path) {
+                Some(photo) => (Some(photo.date), Some(&photo.exif)),
+                None => (None, None),
+            },
+        ).await?;
+
+        // Record which source every date came from
+        for file_move in &mut plan.moves {
+            let Some(photo) = photos.get(&file_move.from) else {
+                continue;
+            };
+            file_move.note = Some(if exif_readable(&file_move.from) {
+                photo.source.describe().to_string()
+            } else {
+                format!("no EXIF readable, {}", photo.source.describe())
+            });
+        }
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok()
+    }
+
+    #[test]
+    fn dates_in_file_names() {
+        assert_eq!(date_from_file_name("IMG_20240315_101112"), at("2024-03-15 10:11:12"));
+        assert_eq!(date_from_file_name("PXL_20231231_235959"), at("2023-12-31 23:59:59"));
+        assert_eq!(date_from_file_name("VID-20240315-WA0001"), at("2024-03-15 00:00:00"));
+        assert_eq!(date_from_file_name("2024-03-15 10.11.12"), at("2024-03-15 10:11:12"));
+        assert_eq!(date_from_file_name("Screenshot 2024_03_15 at 10.11.12"), at("2024-03-15 00:00:00"));
+        assert_eq!(date_from_file_name("scan 1999.12.24"), at("1999-12-24 00:00:00"));
+    }
+
+    #[test]
+    fn implausible_dates_are_ignored() {
+        assert_eq!(date_from_file_name("holiday"), None);
+        assert_eq!(date_from_file_name("IMG_20241350"), None);
+        assert_eq!(date_from_file_name("IMG_20240231"), None);
+        assert_eq!(date_from_file_name("18000101"), None);
+        assert_eq!(date_from_file_name("order 1202403150"), None);
+        assert_eq!(date_from_file_name("20240315_256060"), at("2024-03-15 00:00:00"));
+    }
+}
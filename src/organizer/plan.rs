@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use super::journal::Journal;
+use super::link::{FileLink, replace_with_link};
+use super::{FileMove, OrganizeAction, log_action, quarantine};
+
+/// Everything an organize operation is going to do, computed before touching the disk
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Plan {
+    pub action_type: String,
+    pub moves: Vec<FileMove>,
+    /// Directories created before any file is moved
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub create_dirs: Vec<PathBuf>,
+    /// Directories removed after the moves, if they ended up empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_dirs: Vec<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deletes: Vec<PathBuf>,
+    /// Files replaced by a link to an identical file, after the moves
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<FileLink>,
+    /// The `--quarantine` directory duplicates were moved into, which later
+    /// walks of the folder leave alone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<PathBuf>,
+    /// Destinations already taken by moves of this plan
+    #[serde(skip)]
+    claimed: HashSet<PathBuf>,
+}
+
+impl Plan {
+    pub fn new(action_type: impl Into<String>) -> Self {
+        Plan {
+            action_type: action_type.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a move to `destination`, picking a free name if it is already taken
+    /// on disk or by an earlier move of this plan
+    pub fn add_move(&mut self, from: PathBuf, destination: PathBuf) -> Result<&mut FileMove> {
+        let to = handle_name_conflict(&destination, &self.claimed)?;
+
+        let missing: Vec<&Path> = to.ancestors()
+            .skip(1)
+            .take_while(|dir| !dir.exists())
+            .collect();
+        // Parents come first so the directories can be created in order
+        for dir in missing.into_iter().rev() {
+            if !self.create_dirs.iter().any(|d| d == dir) {
+                self.create_dirs.push(dir.to_path_buf());
+            }
+        }
+
+        self.claimed.insert(to.clone());
+        self.moves.push(FileMove { from, to, hash: None, duplicate_of: None, note: None });
+        Ok(self.moves.last_mut().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty() && self.deletes.is_empty() && self.links.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.moves.is_empty() || (self.deletes.is_empty() && self.links.is_empty()) {
+            parts.push(format!("{} files moved", self.moves.len()));
+        }
+        if !self.deletes.is_empty() {
+            parts.push(format!("{} files deleted", self.deletes.len()));
+        }
+        if !self.links.is_empty() {
+            parts.push(format!("{} files linked", self.links.len()));
+        }
+        parts.join(", ")
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Plan for {}:", self.action_type)?;
+        for dir in &self.create_dirs {
+            writeln!(f, "  mkdir   {}", dir.display())?;
+        }
+        for file_move in &self.moves {
+            writeln!(f, "  move    {} -> {}", file_move.from.display(), file_move.to.display())?;
+            if let Some(kept) = &file_move.duplicate_of {
+                writeln!(f, "          duplicate of {}", kept.display())?;
+            }
+            if let Some(note) = &file_move.note {
+                writeln!(f, "          {}", note)?;
+            }
+        }
+        for path in &self.deletes {
+            writeln!(f, "  delete  {}", path.display())?;
+        }
+        for link in &self.links {
+            writeln!(f, "  link    {} -> {} ({})", link.path.display(), link.target.display(), link.kind)?;
+            if let Some(note) = &link.note {
+                writeln!(f, "          {}", note)?;
+            }
+        }
+        for dir in &self.remove_dirs {
+            writeln!(f, "  rmdir   {} (if empty)", dir.display())?;
+        }
+        write!(f, "{}", self.summary())
+    }
+}
+
+/// Carries out `plan` and records what was actually done in the action log.
+/// Every step goes through the journal first, so an interrupted run can be
+/// finished or rolled back by `journal::recover`.
+pub async fn execute(folder: &Path, plan: Plan) -> Result<OrganizeAction> {
+    let mut journal = Journal::begin(folder, &plan).await?;
+
+    for dir in &plan.create_dirs {
+        fs::create_dir_all(dir).await?;
+        quarantine::hide_root(dir);
+    }
+
+    // Files that vanished since the plan was made are skipped, any other
+    // failure stops the run and leaves the journal for recovery
+    let mut moved = Vec::new();
+    for (index, file_move) in plan.moves.iter().enumerate() {
+        match move_file(&file_move.from, &file_move.to).await {
+            Ok(()) => {
+                journal.moved(index).await?;
+                moved.push(file_move.clone());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !file_move.from.exists() => {
+                journal.skipped(index).await?;
+                eprintln!("Skipped {}, it no longer exists", file_move.from.display());
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Failed to move {} to {}", file_move.from.display(), file_move.to.display())));
+            }
+        }
+    }
+
+    let mut deleted = Vec::new();
+    for (index, path) in plan.deletes.iter().enumerate() {
+        if fs::remove_file(path).await.is_ok() {
+            journal.deleted(index).await?;
+            deleted.push(path.clone());
+        }
+    }
+
+    // Not every filesystem supports every link type, files that can't be
+    // linked are reported and left as they are
+    let mut linked = Vec::new();
+    for (index, link) in plan.links.iter().enumerate() {
+        match replace_with_link(link).await {
+            Ok(()) => {
+                journal.linked(index).await?;
+                linked.push(link.clone());
+            }
+            Err(e) => eprintln!("Could not replace {} with a {}, leaving it as is: {}", link.path.display(), link.kind, e),
+        }
+    }
+
+    for dir in &plan.remove_dirs {
+        let _ = fs::remove_dir(dir).await;
+    }
+
+    let action = OrganizeAction {
+        id: 0,
+        timestamp: journal.timestamp,
+        plan: Plan { moves: moved, deletes: deleted, links: linked, ..plan },
+    };
+    // Nothing to undo if every step failed
+    if !action.plan.is_empty() {
+        log_action(folder, action.clone()).await?;
+    }
+    journal.finish().await?;
+
+    Ok(action)
+}
+
+/// Renames `from` to `to`, copying across volumes when a rename is not possible
+pub async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs::rename(from, to).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to).await?;
+            fs::remove_file(from).await
+        }
+        result => result,
+    }
+}
+
+pub fn handle_name_conflict(path: &Path, claimed: &HashSet<PathBuf>) -> Result<PathBuf> {
+    let is_taken = |p: &Path| p.exists() || claimed.contains(p);
+    if !is_taken(path) {
+        return Ok(path.to_path_buf());
+    }
+
+    let parent = path.parent().unwrap();
+    let filename = path.file_stem().unwrap().to_string_lossy();
+    let extension = path.extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    for i in 1..1000 {
+        let new_name = format!("{} ({}){}", filename, i, extension);
+        let new_path = parent.join(new_name);
+        if !is_taken(&new_path) {
+            return Ok(new_path);
+        }
+    }
+
+    Err(anyhow::anyhow!("Could not resolve name conflict for: {}", path.display()))
+}
@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::Result;
+use chrono::{Local, NaiveDateTime, TimeZone};
+use tokio::fs;
+
+use super::hide_file;
+
+const QUARANTINE_DIR: &str = ".organize_quarantine";
+const BATCH_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+/// Where quarantined files of `folder` go: the configured global location or
+/// a hidden directory inside the folder itself
+pub fn quarantine_root(folder: &Path, global: Option<&Path>) -> PathBuf {
+    match global {
+        Some(dir) => dir.to_path_buf(),
+        None => folder.join(QUARANTINE_DIR),
+    }
+}
+
+/// Sets the hidden attribute on a quarantine directory inside a folder once it
+/// has been created
+pub fn hide_root(dir: &Path) {
+    if dir.file_name().is_some_and(|name| name == QUARANTINE_DIR) {
+        hide_file(dir);
+    }
+}
+
+/// A new timestamped batch directory, one per organize action. Batches in a
+/// global location are further split by the name of the organized folder.
+pub fn new_batch(folder: &Path, global: Option<&Path>) -> PathBuf {
+    let batch = quarantine_root(folder, global).join(Local::now().format(BATCH_FORMAT).to_string());
+    match (global, folder.file_name()) {
+        (Some(_), Some(name)) => batch.join(name),
+        _ => batch,
+    }
+}
+
+/// Parses durations like `30d`, `12h`, `90m` or a plain number of days
+pub fn parse_age(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "d"),
+    };
+
+    let number: u64 = number.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid age '{}', expected something like 30d or 12h", value))?;
+    let seconds = match unit {
+        "d" => number * 24 * 60 * 60,
+        "h" => number * 60 * 60,
+        "m" => number * 60,
+        _ => anyhow::bail!("Invalid age unit '{}', expected d, h or m", unit),
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Permanently deletes quarantine batches older than `older_than`, or only
+/// lists them for a dry run
+pub async fn purge(root: &Path, older_than: Duration, dry_run: bool) -> Result<()> {
+    if !root.exists() {
+        println!("No quarantined files found in {}", root.display());
+        return Ok(());
+    }
+
+    let cutoff = Local::now() - chrono::Duration::from_std(older_than)?;
+    let mut purged = 0;
+    let mut kept = 0;
+
+    let mut entries = fs::read_dir(root).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(created) = batch_time(&path) else {
+            continue;
+        };
+
+        if created >= cutoff {
+            kept += 1;
+            continue;
+        }
+        if dry_run {
+            println!("Would purge {}", path.display());
+            purged += 1;
+            continue;
+        }
+
+        match fs::remove_dir_all(&path).await {
+            Ok(()) => purged += 1,
+            Err(e) => eprintln!("Failed to purge {}: {}", path.display(), e),
+        }
+    }
+
+    if dry_run {
+        println!("Would purge {} quarantine batches, keep {}", purged, kept);
+        return Ok(());
+    }
+
+    // Don't leave an empty quarantine directory behind
+    let _ = fs::remove_dir(root).await;
+
+    println!("Purged {} quarantine batches, kept {}", purged, kept);
+    Ok(())
+}
+
+fn batch_time(path: &Path) -> Option<chrono::DateTime<Local>> {
+    if !path.is_dir() {
+        return None;
+    }
+    let name = path.file_name()?.to_string_lossy();
+    let naive = NaiveDateTime::parse_from_str(&name, BATCH_FORMAT).ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_with_units() {
+        assert_eq!(parse_age("30d").unwrap(), Duration::from_secs(30 * 24 * 60 * 60));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(parse_age(" 90m ").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_age("7").unwrap(), Duration::from_secs(7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn malformed_ages() {
+        assert!(parse_age("").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("-1d").is_err());
+        assert!(parse_age("1.5d").is_err());
+        assert!(parse_age("30x").unwrap_err().to_string().contains("Invalid age unit"));
+    }
+}
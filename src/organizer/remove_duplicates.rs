@@ -2,77 +2,351 @@ use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use anyhow::Result;
 use tokio::fs;
-use tokio::io::AsyncReadExt;
+use std::io::SeekFrom;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use sha2::{Sha256, Digest};
-use chrono::Utc;
+use async_trait::async_trait;
 
-use super::{OrganizeAction, FileMove, log_action};
+use super::hash_cache::{HashCache, HashKind};
+use super::keep_policy::KeepPolicy;
+use super::link::{FileLink, LinkKind, is_same_file};
+use super::report::{self, ReportFormat};
+use super::{ModeOption, ModeOptions, Organizer, Plan, Walk, collect_entries, quarantine};
 
-pub async fn remove_duplicates(folder: &Path) -> Result<()> {
-    let mut file_hashes: HashMap<String, Vec<PathBuf>> = HashMap::new();
-    let mut files_to_delete = Vec::new();
-    let mut moves = Vec::new();
+static OPTIONS: &[ModeOption] = &[
+    ModeOption {
+        name: "quarantine",
+        short: None,
+        value_name: "DIR",
+        help: "Move duplicates into this directory instead of the folder's hidden .organize_quarantine (relative paths start at the folder)",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "keep",
+        short: None,
+        value_name: "POLICY",
+        help: "Which copy to keep: oldest, newest, shortest_path, fewest_markers, preferred_dir or longest_name",
+        default: Some("oldest"),
+        flag: false,
+    },
+    ModeOption {
+        name: "prefer",
+        short: None,
+        value_name: "DIR",
+        help: "Directory whose copies are kept with --keep preferred_dir",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "reference",
+        short: None,
+        value_name: "DIR",
+        help: "Only remove files that also exist in this library, which itself is never touched",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "link",
+        short: None,
+        value_name: "TYPE",
+        help: "Replace duplicates with a hard, symlink or reflink link to the kept file instead of moving them",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "report",
+        short: None,
+        value_name: "FORMAT",
+        help: "Only print a json, csv or html report of the duplicate groups, nothing is moved",
+        default: None,
+        flag: false,
+    },
+    ModeOption {
+        name: "no-cache",
+        short: None,
+        value_name: "",
+        help: "Hash every file again instead of reusing digests from .organize_hash_cache.json",
+        default: None,
+        flag: true,
+    },
+];
 
-    let mut all_files = Vec::new();
-    collect_all_files_recursively(folder, &mut all_files).await?;
+/// Bytes hashed at each end of a file before committing to a full hash
+const PARTIAL_BLOCK: u64 = 64 * 1024;
+const MAX_PARALLEL_HASHES: usize = 8;
 
-    for path in &all_files {
-        if let Ok(hash) = calculate_hash(path).await {
-            file_hashes.entry(hash).or_default().push(path.clone());
-        }
+/// Files with identical content
+pub(crate) struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+pub struct RemoveDuplicates;
+
+#[async_trait]
+impl Organizer for RemoveDuplicates {
+    fn name(&self) -> &'static str {
+        "remove_duplicates"
     }
 
-    for (_, paths) in file_hashes {
-        if paths.len() > 1 {
-            for path_to_delete in paths.into_iter().skip(1) {
-                if let Ok(_) = fs::remove_file(&path_to_delete).await {
-                    files_to_delete.push(path_to_delete);
+    fn recursive(&self) -> bool {
+        true
+    }
+
+    fn action_type(&self, _options: &ModeOptions) -> String {
+        "remove_duplicates".to_string()
+    }
+
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
+    }
+
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        let global = options.get::<PathBuf>("quarantine").map(|dir| folder.join(dir));
+        let prefer = options.get::<PathBuf>("prefer").map(|dir| folder.join(dir));
+        let policy = KeepPolicy::parse(&options.get::<String>("keep").unwrap_or_default(), prefer)?;
+        let link = options.get::<String>("link").map(|kind| kind.parse::<LinkKind>()).transpose()?;
+
+        let reference = reference_dir(folder, options)?;
+
+        let batch = quarantine::new_batch(folder, global.as_deref());
+        let mut plan = Plan::new(self.action_type(options));
+        plan.quarantine = global.clone();
+
+        for group in find_cached_duplicates(folder, reference.as_deref(), options).await? {
+            // With a reference library every copy outside of it goes
+            let (kept, note) = match &reference {
+                Some(reference) => {
+                    let originals: Vec<PathBuf> = group.paths.iter().filter(|p| p.starts_with(reference)).cloned().collect();
+                    let (keep, _) = policy.choose(&originals);
+                    (originals[keep].clone(), "found in the reference library".to_string())
                 }
+                None => {
+                    let (keep, reason) = policy.choose(&group.paths);
+                    (group.paths[keep].clone(), format!("kept {}", reason))
+                }
+            };
+            let kept = &kept;
+
+            for path in &group.paths {
+                if path == kept || reference.as_ref().is_some_and(|r| path.starts_with(r)) {
+                    continue;
+                }
+
+                if let Some(kind) = link {
+                    // Hard links from an earlier run share the kept file already
+                    if kind == LinkKind::Hard && is_same_file(path, kept) {
+                        continue;
+                    }
+                    plan.links.push(FileLink {
+                        path: path.clone(),
+                        target: kept.clone(),
+                        kind,
+                        hash: Some(group.hash.clone()),
+                        note: Some(note.clone()),
+                    });
+                    continue;
+                }
+
+                let relative = path.strip_prefix(folder).unwrap();
+                let file_move = plan.add_move(path.clone(), batch.join(relative))?;
+                file_move.hash = Some(group.hash.clone());
+                file_move.duplicate_of = Some(kept.clone());
+                file_move.note = Some(note.clone());
             }
         }
+
+        Ok(plan)
     }
 
-    for path in files_to_delete {
-        moves.push(FileMove {
-            from: path.clone(),
-            to: PathBuf::from(format!("DELETED::{}", path.display())),
-        });
+    async fn report(&self, folder: &Path, format: &str, options: &ModeOptions) -> Result<String> {
+        let format: ReportFormat = format.parse()?;
+        let reference = reference_dir(folder, options)?;
+        let groups = find_cached_duplicates(folder, reference.as_deref(), options).await?;
+        report::render(folder, reference.as_deref(), &groups, format)
+    }
+}
+
+/// The `--reference` library, relative paths are taken from `folder`
+fn reference_dir(folder: &Path, options: &ModeOptions) -> Result<Option<PathBuf>> {
+    let Some(reference) = options.get::<PathBuf>("reference").map(|dir| folder.join(dir)) else {
+        return Ok(None);
+    };
+    if !reference.is_dir() {
+        anyhow::bail!("Reference library is not a directory: {}", reference.display());
     }
+    Ok(Some(reference))
+}
 
-    let action = OrganizeAction {
-        timestamp: Utc::now(),
-        action_type: "remove_duplicates".to_string(),
-        moves,
+/// `find_duplicates` with the hash cache of `folder`, unless `--no-cache` was given
+async fn find_cached_duplicates(folder: &Path, reference: Option<&Path>, options: &ModeOptions) -> Result<Vec<DuplicateGroup>> {
+    let mut cache = if options.get::<bool>("no-cache").unwrap_or(false) {
+        HashCache::disabled()
+    } else {
+        HashCache::load(folder).await
     };
-    log_action(folder, action.clone()).await?;
 
-    println!("Successfully removed {} duplicate files", action.moves.len());
-    Ok(())
+    // Links made by `--link symlink` point at the kept file
+    let walk = Walk::from_options(&RemoveDuplicates, folder, options).await?.without_links();
+    let groups = find_duplicates(folder, reference, &walk, &mut cache).await?;
+
+    // Previews and reports leave the folder as it is, cache included
+    let read_only = options.get::<String>("dry-run").is_some() || options.get::<String>("report").is_some();
+    if !read_only {
+        if let Err(e) = cache.save().await {
+            eprintln!("Failed to save hash cache: {}", e);
+        }
+    }
+    Ok(groups)
+}
+
+/// Groups the files under `folder` by content, leaving out unique files.
+/// With a `reference` library only groups with files both inside and outside
+/// of it are returned.
+///
+/// Only files sharing their exact size can be duplicates, and of those only
+/// the ones whose first and last 64 KB match are read in full.
+pub(crate) async fn find_duplicates(
+    folder: &Path,
+    reference: Option<&Path>,
+    walk: &Walk,
+    cache: &mut HashCache,
+) -> Result<Vec<DuplicateGroup>> {
+    let mut all_files = collect_entries(folder, walk).await?;
+    if let Some(reference) = reference {
+        // The library may be inside the folder, its files must only be listed once
+        all_files.retain(|entry| !entry.path.starts_with(reference));
+        all_files.extend(collect_entries(reference, &Walk::default().without_links()).await?);
+    }
+
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for entry in all_files {
+        by_size.entry(entry.metadata.len()).or_default().push(entry.path);
+    }
+    by_size.retain(|_, paths| paths.len() > 1);
+
+    // Every stage hashes the candidates of all sizes together, so the tasks
+    // stay busy however the sizes are spread
+    let candidates: Vec<PathBuf> = by_size.values().flatten().cloned().collect();
+    let partials = hash_files(candidates, HashKind::Partial, cache).await;
+
+    let mut by_partial: HashMap<(u64, String), Vec<PathBuf>> = HashMap::new();
+    for (size, paths) in by_size {
+        for path in paths {
+            if let Some(partial) = partials.get(&path) {
+                by_partial.entry((size, partial.clone())).or_default().push(path);
+            }
+        }
+    }
+    by_partial.retain(|_, paths| paths.len() > 1);
+
+    // Small files were hashed whole by `partial_hash` already
+    let candidates: Vec<PathBuf> = by_partial.iter()
+        .filter(|((size, _), _)| *size > 2 * PARTIAL_BLOCK)
+        .flat_map(|(_, paths)| paths.clone())
+        .collect();
+    let fulls = hash_files(candidates, HashKind::Full, cache).await;
+
+    let mut groups = Vec::new();
+    for ((size, partial), paths) in by_partial {
+        let mut full_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        if size <= 2 * PARTIAL_BLOCK {
+            full_groups.insert(partial, paths);
+        } else {
+            for path in paths {
+                if let Some(full) = fulls.get(&path) {
+                    full_groups.entry(full.clone()).or_default().push(path);
+                }
+            }
+        }
+
+        for (hash, mut paths) in full_groups {
+            let in_reference = paths.iter().filter(|p| reference.is_some_and(|r| p.starts_with(r))).count();
+            if paths.len() > 1 && (reference.is_none() || (in_reference > 0 && in_reference < paths.len())) {
+                paths.sort();
+                groups.push(DuplicateGroup { hash, size, paths });
+            }
+        }
+    }
+    groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+
+    Ok(groups)
 }
 
-async fn collect_all_files_recursively(current_dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
-    let mut entries = fs::read_dir(current_dir).await?;
-    while let Some(entry) = entries.next_entry().await? {
-        let path = entry.path();
-        if path.is_dir() {
-            Box::pin(collect_all_files_recursively(&path, files)).await?;
-        } else if path.is_file() {
-            files.push(path);
+/// Hashes `paths` on up to `MAX_PARALLEL_HASHES` tasks. Digests still valid in
+/// `cache` are reused, files that can't be read are left out.
+async fn hash_files(paths: Vec<PathBuf>, kind: HashKind, cache: &mut HashCache) -> HashMap<PathBuf, String> {
+    let permits = Arc::new(Semaphore::new(MAX_PARALLEL_HASHES));
+    let mut tasks = JoinSet::new();
+    let mut digests = HashMap::new();
+
+    for path in paths {
+        if let Some(digest) = cache.get(&path, kind) {
+            digests.insert(path, digest);
+            continue;
+        }
+
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let digest = match kind {
+                HashKind::Partial => partial_hash(&path).await,
+                HashKind::Full => calculate_hash(&path).await,
+            };
+            (path, digest)
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((path, Ok(digest))) = joined {
+            cache.insert(&path, kind, digest.clone());
+            digests.insert(path, digest);
         }
     }
-    Ok(())
+    digests
 }
 
-async fn calculate_hash(path: &Path) -> Result<String> {
+pub(crate) async fn calculate_hash(path: impl AsRef<Path>) -> Result<String> {
     let mut file = fs::File::open(path).await?;
     let mut hasher = Sha256::new();
-    let mut buffer = [0; 4096];
+    let mut buffer = vec![0; 64 * 1024];
 
-    while let Ok(n) = file.read(&mut buffer).await {
+    loop {
+        let n = file.read(&mut buffer).await?;
         if n == 0 { break; }
         hasher.update(&buffer[..n]);
     }
 
-    let hash_bytes = hasher.finalize();
-    Ok(hash_bytes.iter().map(|b| format!("{:02x}", b)).collect())
-}
\ No newline at end of file
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// SHA-256 of the first and last `PARTIAL_BLOCK` bytes, or of the whole file
+/// if it is not larger than both blocks together
+async fn partial_hash(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    if size <= 2 * PARTIAL_BLOCK {
+        drop(file);
+        return calculate_hash(path).await;
+    }
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; PARTIAL_BLOCK as usize];
+
+    file.read_exact(&mut buffer).await?;
+    hasher.update(&buffer);
+    file.seek(SeekFrom::End(-(PARTIAL_BLOCK as i64))).await?;
+    file.read_exact(&mut buffer).await?;
+    hasher.update(&buffer);
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,278 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::Result;
+use serde::Serialize;
+
+use super::by_type::Mismatch;
+use super::near_duplicates::NearDuplicateGroup;
+use super::remove_duplicates::DuplicateGroup;
+
+/// Output format of `--report`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            "html" => Ok(ReportFormat::Html),
+            _ => Err(anyhow::anyhow!("Invalid report format '{}', use json, csv or html", value)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    folder: &'a Path,
+    /// Library whose files are never removed, see `--reference`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reference: Option<&'a Path>,
+    groups: Vec<ReportGroup<'a>>,
+    /// Bytes freed by keeping a single copy of every group
+    total_reclaimable: u64,
+}
+
+#[derive(Serialize)]
+struct ReportGroup<'a> {
+    id: usize,
+    size: u64,
+    digest: &'a str,
+    paths: &'a [PathBuf],
+    reclaimable: u64,
+}
+
+/// Renders the duplicate groups found by `remove_duplicates`
+pub fn render(folder: &Path, reference: Option<&Path>, groups: &[DuplicateGroup], format: ReportFormat) -> Result<String> {
+    let groups: Vec<ReportGroup> = groups.iter().enumerate()
+        .map(|(index, group)| {
+            // Every copy but one, or every copy outside the reference library
+            let removable = match reference {
+                Some(reference) => group.paths.iter().filter(|p| !p.starts_with(reference)).count(),
+                None => group.paths.len() - 1,
+            };
+            ReportGroup {
+                id: index + 1,
+                size: group.size,
+                digest: &group.hash,
+                paths: &group.paths,
+                reclaimable: group.size * removable as u64,
+            }
+        })
+        .collect();
+    let total_reclaimable = groups.iter().map(|g| g.reclaimable).sum();
+    let report = Report { folder, reference, groups, total_reclaimable };
+
+    Ok(match format {
+        ReportFormat::Json => serde_json::to_string_pretty(&report)?,
+        ReportFormat::Csv => render_csv(&report),
+        ReportFormat::Html => render_html(&report),
+    })
+}
+
+#[derive(Serialize)]
+struct NearReport<'a> {
+    folder: &'a Path,
+    groups: Vec<NearReportGroup<'a>>,
+}
+
+#[derive(Serialize)]
+struct NearReportGroup<'a> {
+    id: usize,
+    /// The highest resolution image of the group
+    keeper: &'a Path,
+    images: Vec<NearReportImage<'a>>,
+}
+
+#[derive(Serialize)]
+struct NearReportImage<'a> {
+    path: &'a Path,
+    width: u32,
+    height: u32,
+    size: u64,
+    /// Hamming distance to the keeper's fingerprint
+    distance: u32,
+}
+
+/// Renders the groups of similar images found by `near_duplicates`
+pub fn render_near_duplicates(folder: &Path, groups: &[NearDuplicateGroup], format: ReportFormat) -> Result<String> {
+    let report = NearReport {
+        folder,
+        groups: groups.iter().enumerate()
+            .map(|(index, group)| NearReportGroup {
+                id: index + 1,
+                keeper: &group.images[0].path,
+                images: group.images.iter().enumerate()
+                    .map(|(i, image)| NearReportImage {
+                        path: &image.path,
+                        width: image.width,
+                        height: image.height,
+                        size: image.size,
+                        distance: group.distance(i),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+
+    Ok(match format {
+        ReportFormat::Json => serde_json::to_string_pretty(&report)?,
+        ReportFormat::Csv => render_near_csv(&report),
+        ReportFormat::Html => render_near_html(&report),
+    })
+}
+
+fn render_near_csv(report: &NearReport) -> String {
+    let mut out = String::from("group,keeper,width,height,size,distance,path\n");
+    for group in &report.groups {
+        for image in &group.images {
+            let _ = writeln!(out, "{},{},{},{},{},{},{}",
+                group.id, image.path == group.keeper, image.width, image.height, image.size, image.distance,
+                csv_field(&image.path.to_string_lossy()));
+        }
+    }
+    out
+}
+
+fn render_near_html(report: &NearReport) -> String {
+    let folder = html_escape(&report.folder.to_string_lossy());
+    let mut out = html_header(&format!("Similar images in {}", folder));
+    let _ = writeln!(out, "<p>{} groups</p>", report.groups.len());
+
+    let _ = writeln!(out, "<table>\n<tr><th>Group</th><th>Resolution</th><th>Size</th><th>Distance</th><th>Path</th></tr>");
+    for group in &report.groups {
+        for image in &group.images {
+            let (class, id) = if image.path == group.keeper {
+                (" class=\"group\"", group.id.to_string())
+            } else {
+                ("", String::new())
+            };
+            let _ = writeln!(out, "<tr{}><td>{}</td><td>{}x{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                class, id, image.width, image.height, format_size(image.size), image.distance,
+                html_escape(&image.path.to_string_lossy()));
+        }
+    }
+    let _ = writeln!(out, "</table>\n</body>\n</html>");
+    out
+}
+
+/// One row per file, the group columns repeat for every file of a group. A
+/// last `total` row holds the reclaimable bytes of all groups.
+fn render_csv(report: &Report) -> String {
+    let mut out = String::from("group,size,digest,reclaimable,path\n");
+    for group in &report.groups {
+        for path in group.paths {
+            let _ = writeln!(out, "{},{},{},{},{}",
+                group.id, group.size, group.digest, group.reclaimable, csv_field(&path.to_string_lossy()));
+        }
+    }
+    let _ = writeln!(out, "total,,,{},", report.total_reclaimable);
+    out
+}
+
+fn render_html(report: &Report) -> String {
+    let folder = html_escape(&report.folder.to_string_lossy());
+    let mut out = html_header(&format!("Duplicates in {}", folder));
+    let _ = writeln!(out, "<p>{} groups, {} reclaimable</p>", report.groups.len(), format_size(report.total_reclaimable));
+
+    let _ = writeln!(out, "<table>\n<tr><th>Group</th><th>Size</th><th>Reclaimable</th><th>Digest</th></tr>");
+    for group in &report.groups {
+        let _ = writeln!(out, "<tr class=\"group\"><td>{}</td><td>{}</td><td>{}</td><td><code>{}</code></td></tr>",
+            group.id, format_size(group.size), format_size(group.reclaimable), group.digest);
+        for path in group.paths {
+            let _ = writeln!(out, "<tr><td></td><td colspan=\"3\">{}</td></tr>", html_escape(&path.to_string_lossy()));
+        }
+    }
+    let _ = writeln!(out, "</table>\n</body>\n</html>");
+    out
+}
+
+#[derive(Serialize)]
+struct MismatchReport<'a> {
+    folder: &'a Path,
+    files: Vec<MismatchFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct MismatchFile<'a> {
+    path: &'a Path,
+    extension: &'a str,
+    detected: &'a str,
+}
+
+/// Renders the files of the `type` mode whose content does not match their extension
+pub fn render_mismatches(folder: &Path, mismatches: &[Mismatch], format: ReportFormat) -> Result<String> {
+    let report = MismatchReport {
+        folder,
+        files: mismatches.iter()
+            .map(|m| MismatchFile { path: &m.path, extension: &m.extension, detected: m.detected })
+            .collect(),
+    };
+
+    Ok(match format {
+        ReportFormat::Json => serde_json::to_string_pretty(&report)?,
+        ReportFormat::Csv => {
+            let mut out = String::from("extension,detected,path\n");
+            for file in &report.files {
+                let _ = writeln!(out, "{},{},{}", csv_field(file.extension), file.detected, csv_field(&file.path.to_string_lossy()));
+            }
+            out
+        }
+        ReportFormat::Html => {
+            let mut out = html_header(&format!("Extension mismatches in {}", html_escape(&folder.to_string_lossy())));
+            let _ = writeln!(out, "<p>{} files</p>", report.files.len());
+            let _ = writeln!(out, "<table>\n<tr><th>Extension</th><th>Detected</th><th>Path</th></tr>");
+            for file in &report.files {
+                let _ = writeln!(out, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    html_escape(file.extension), file.detected, html_escape(&file.path.to_string_lossy()));
+            }
+            let _ = writeln!(out, "</table>\n</body>\n</html>");
+            out
+        }
+    })
+}
+
+/// Document head and heading, `title` has to be escaped already
+fn html_header(title: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">");
+    let _ = writeln!(out, "<title>{}</title>", title);
+    let _ = writeln!(out, "<style>body {{ font-family: sans-serif; }} td, th {{ padding: 2px 8px; text-align: left; }} \
+        tr.group {{ background: #eee; }}</style>\n</head>\n<body>");
+    let _ = writeln!(out, "<h1>{}</h1>", title);
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
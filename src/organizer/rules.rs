@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, NaiveDate};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use super::quarantine::parse_age;
+use super::{Entry, Layout, ModeOption, ModeOptions, Organizer, Plan, TimeZone, plan_with, user_config_file};
+
+static OPTIONS: &[ModeOption] = &[ModeOption {
+    name: "rules",
+    short: None,
+    value_name: "FILE",
+    help: "Rules file to use instead of the folder's .organize.toml or the user default",
+    default: None,
+    flag: false,
+}];
+
+/// Rules files looked up in the organized folder, in order
+const FOLDER_RULES: &[&str] = &[".organize.toml", ".organize.json"];
+
+/// A rules file as written by the user
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, alias = "rules")]
+    rule: Vec<RuleSpec>,
+}
+
+/// One `[[rule]]` entry. All given conditions have to match.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: Option<String>,
+    /// Folder the matching files are moved into, relative to the organized
+    /// folder. May use the placeholders of `--layout`.
+    destination: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    glob: Option<String>,
+    regex: Option<String>,
+    min_size: Option<String>,
+    max_size: Option<String>,
+    older_than: Option<String>,
+    newer_than: Option<String>,
+    modified_after: Option<String>,
+    modified_before: Option<String>,
+    created_after: Option<String>,
+    created_before: Option<String>,
+}
+
+/// A validated rule
+#[derive(Debug)]
+struct Rule {
+    destination: Layout,
+    extensions: Vec<String>,
+    glob: Option<GlobMatcher>,
+    /// A glob without a slash matches the file name at any depth, like in
+    /// `.organizeignore`, the others the path relative to the folder
+    glob_on_name: bool,
+    regex: Option<Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    older_than: Option<Duration>,
+    newer_than: Option<Duration>,
+    modified_after: Option<NaiveDate>,
+    modified_before: Option<NaiveDate>,
+    created_after: Option<NaiveDate>,
+    created_before: Option<NaiveDate>,
+}
+
+/// The rules of a folder, evaluated first match wins
+#[derive(Debug)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Loads `explicit` if given, otherwise the folder's own rules file or
+    /// the user-level default
+    pub async fn load(folder: &Path, explicit: Option<&Path>) -> Result<Self> {
+        let candidates: Vec<PathBuf> = match explicit {
+            Some(path) => vec![path.to_path_buf()],
+            None => FOLDER_RULES.iter().map(|name| folder.join(name)).chain(user_config_file("rules.toml")).collect(),
+        };
+
+        let Some(path) = candidates.iter().find(|p| p.is_file()) else {
+            anyhow::bail!(
+                "No rules file found, create .organize.toml in the folder{}",
+                user_config_file("rules.toml").map(|p| format!(" or {}", p.display())).unwrap_or_default()
+            );
+        };
+
+        let content = tokio::fs::read_to_string(path).await
+            .with_context(|| format!("Failed to read rules file {}", path.display()))?;
+        Rules::parse(&content, path)
+    }
+
+    fn parse(content: &str, path: &Path) -> Result<Self> {
+        let file: RulesFile = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str(content).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(content).map_err(anyhow::Error::from)
+        }
+        .with_context(|| format!("Invalid rules file {}", path.display()))?;
+
+        let rules = file.rule.into_iter().enumerate()
+            .map(|(index, spec)| {
+                let label = match &spec.name {
+                    Some(name) => format!("rule {} ({})", index + 1, name),
+                    None => format!("rule {}", index + 1),
+                };
+                Rule::compile(spec).with_context(|| format!("{}: {}", path.display(), label))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if rules.is_empty() {
+            anyhow::bail!("{}: no rules defined", path.display());
+        }
+        Ok(Rules { rules })
+    }
+
+    /// Destination of `entry` relative to the organized folder, from the first
+    /// matching rule, with the dates of its template shown in `timezone`
+    pub fn destination(&self, entry: &Entry, timezone: TimeZone) -> Option<PathBuf> {
+        let rule = self.rules.iter().find(|rule| rule.matches(entry))?;
+        let modified = entry.metadata.modified().ok().map(|time| timezone.wall_clock(time));
+        let folder = rule.destination.render(entry, modified, timezone, None);
+        Some(folder.join(entry.path.file_name()?))
+    }
+}
+
+impl Rule {
+    fn compile(spec: RuleSpec) -> Result<Self> {
+        if spec.destination.is_empty() {
+            anyhow::bail!("destination is empty");
+        }
+        let destination: Layout = spec.destination.parse()?;
+
+        let normalized = spec.glob.as_deref().map(|pattern| pattern.replace('\\', "/"));
+        let glob_on_name = normalized.as_deref().is_some_and(|pattern| !pattern.contains('/'));
+        let glob = normalized.as_deref()
+            .map(|pattern| GlobBuilder::new(pattern.trim_start_matches('/')).case_insensitive(true).literal_separator(true).build()
+                .map(|g| g.compile_matcher())
+                .with_context(|| format!("invalid glob '{}'", pattern)))
+            .transpose()?;
+        let regex = spec.regex.as_deref()
+            .map(|pattern| RegexBuilder::new(pattern).case_insensitive(true).build()
+                .with_context(|| format!("invalid regex '{}'", pattern)))
+            .transpose()?;
+
+        let size = |value: &Option<String>, field: &str| value.as_deref()
+            .map(|v| parse_size(v).with_context(|| format!("invalid {}", field)))
+            .transpose();
+        let age = |value: &Option<String>, field: &str| value.as_deref()
+            .map(|v| parse_age(v).with_context(|| format!("invalid {}", field)))
+            .transpose();
+        let date = |value: &Option<String>, field: &str| value.as_deref()
+            .map(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                .with_context(|| format!("invalid {} '{}', expected YYYY-MM-DD", field, v)))
+            .transpose();
+
+        Ok(Rule {
+            destination,
+            extensions: spec.extensions.iter()
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .collect(),
+            glob,
+            glob_on_name,
+            regex,
+            min_size: size(&spec.min_size, "min_size")?,
+            max_size: size(&spec.max_size, "max_size")?,
+            older_than: age(&spec.older_than, "older_than")?,
+            newer_than: age(&spec.newer_than, "newer_than")?,
+            modified_after: date(&spec.modified_after, "modified_after")?,
+            modified_before: date(&spec.modified_before, "modified_before")?,
+            created_after: date(&spec.created_after, "created_after")?,
+            created_before: date(&spec.created_before, "created_before")?,
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        let name = entry.path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let size = entry.metadata.len();
+        let modified = entry.metadata.modified().ok();
+        let created = entry.metadata.created().ok();
+
+        if !self.extensions.is_empty() {
+            let extension = entry.path.extension().map(|e| e.to_string_lossy().to_lowercase());
+            if !extension.is_some_and(|e| self.extensions.contains(&e)) {
+                return false;
+            }
+        }
+
+        self.glob.as_ref().is_none_or(|glob| if self.glob_on_name { glob.is_match(&*name) } else { glob.is_match(&entry.relative) })
+            && self.regex.as_ref().is_none_or(|regex| regex.is_match(&name))
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self.older_than.is_none_or(|age| age_of(modified).is_some_and(|a| a >= age))
+            && self.newer_than.is_none_or(|age| age_of(modified).is_some_and(|a| a < age))
+            && self.modified_after.is_none_or(|d| local_date(modified).is_some_and(|m| m >= d))
+            && self.modified_before.is_none_or(|d| local_date(modified).is_some_and(|m| m < d))
+            && self.created_after.is_none_or(|d| local_date(created).is_some_and(|c| c >= d))
+            && self.created_before.is_none_or(|d| local_date(created).is_some_and(|c| c < d))
+    }
+}
+
+fn age_of(time: Option<SystemTime>) -> Option<Duration> {
+    SystemTime::now().duration_since(time?).ok()
+}
+
+fn local_date(time: Option<SystemTime>) -> Option<NaiveDate> {
+    Some(DateTime::<Local>::from(time?).date_naive())
+}
+
+/// Parses sizes like `500`, `10KB`, `1.5 MB` or `2GB`, using 1024 as the unit step
+pub fn parse_size(value: &str) -> Result<u64> {
+    parse_size_in(value, 1024)
+}
+
+/// Like `parse_size` with `step` bytes per KB, 1000 for SI units. `KiB`, `MiB`
+/// and so on always step by 1024.
+pub fn parse_size_in(value: &str, step: u64) -> Result<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: f64 = number.parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size '{}', expected something like 500KB or 2GB", value))?;
+    let unit = unit.trim().to_uppercase();
+    let (power, step) = match unit.strip_suffix("IB") {
+        Some(prefix) => (prefix, 1024),
+        None => (unit.strip_suffix('B').unwrap_or(&unit), step),
+    };
+    let exponent = match power {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => anyhow::bail!("Invalid size unit '{}', expected B, KB, MB, GB or TB", unit),
+    };
+    Ok((number * step.pow(exponent) as f64) as u64)
+}
+
+pub struct ByRules;
+
+#[async_trait]
+impl Organizer for ByRules {
+    fn name(&self) -> &'static str {
+        "rules"
+    }
+
+    fn options(&self) -> &'static [ModeOption] {
+        OPTIONS
+    }
+
+    fn action_type(&self, _options: &ModeOptions) -> String {
+        "rules".to_string()
+    }
+
+    async fn plan(&self, folder: &Path, options: &ModeOptions) -> Result<Plan> {
+        let explicit = options.get::<PathBuf>("rules");
+        let rules = Rules::load(folder, explicit.as_deref()).await?;
+        let timezone = TimeZone::from_options(options)?;
+        plan_with(self, folder, options, |entry| rules.destination(entry, timezone)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An entry for `relative` in a folder of its own
+    fn entry(relative: &str) -> Entry {
+        let folder = std::env::temp_dir().join(format!("organizer-rules-{}", std::process::id()));
+        let path = folder.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "").unwrap();
+        Entry { metadata: std::fs::metadata(&path).unwrap(), relative: PathBuf::from(relative), path }
+    }
+
+    fn glob_rule(glob: &str) -> Rules {
+        let content = format!("[[rule]]\ndestination = \"Matched\"\nglob = '{}'\n", glob);
+        Rules::parse(&content, Path::new(".organize.toml")).unwrap()
+    }
+
+    #[test]
+    fn globs_without_a_slash_match_the_name_at_any_depth() {
+        let rules = glob_rule("*.pdf");
+        assert!(rules.destination(&entry("a.pdf"), TimeZone::Utc).is_some());
+        assert!(rules.destination(&entry("sub/deeper/b.PDF"), TimeZone::Utc).is_some());
+        assert!(rules.destination(&entry("sub/c.txt"), TimeZone::Utc).is_none());
+    }
+
+    #[test]
+    fn globs_with_a_slash_match_the_relative_path() {
+        let rules = glob_rule("sub/*.pdf");
+        assert!(rules.destination(&entry("sub/a.pdf"), TimeZone::Utc).is_some());
+        assert!(rules.destination(&entry("a.pdf"), TimeZone::Utc).is_none());
+        assert!(rules.destination(&entry("sub/deeper/b.pdf"), TimeZone::Utc).is_none());
+        assert!(glob_rule("/sub/*.pdf").destination(&entry("sub/a.pdf"), TimeZone::Utc).is_some());
+    }
+
+    #[test]
+    fn sizes_with_units() {
+        assert_eq!(parse_size("10").unwrap(), 10);
+        assert_eq!(parse_size("500KB").unwrap(), 500 * 1024);
+        assert_eq!(parse_size(" 4 kb ").unwrap(), 4 * 1024);
+        assert_eq!(parse_size("1.5MB").unwrap(), 1024 * 1024 * 3 / 2);
+        assert_eq!(parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1TB").unwrap(), 1024u64.pow(4));
+    }
+
+    #[test]
+    fn si_and_binary_units() {
+        assert_eq!(parse_size_in("10MB", 1000).unwrap(), 10_000_000);
+        assert_eq!(parse_size_in("1KiB", 1000).unwrap(), 1024);
+        assert_eq!(parse_size_in("3MiB", 1024).unwrap(), 3 * 1024 * 1024);
+    }
+
+    #[test]
+    fn malformed_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("abc").is_err());
+        assert!(parse_size("-5MB").is_err());
+        assert!(parse_size("10XB").unwrap_err().to_string().contains("Invalid size unit"));
+        assert!(parse_size("1..5MB").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes read from the start of a file, enough to find the first entries of a ZIP
+const HEADER_LEN: usize = 4096;
+
+/// A file type recognized from its content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sniffed {
+    /// Usual extension of the type, used to pick its category
+    pub extension: &'static str,
+    /// Extensions that legitimately carry this content
+    pub also: &'static [&'static str],
+}
+
+impl Sniffed {
+    const fn new(extension: &'static str, also: &'static [&'static str]) -> Self {
+        Sniffed { extension, also }
+    }
+
+    /// Whether a file with the lowercase extension `ext` may contain this type
+    pub fn accepts(&self, ext: &str) -> bool {
+        self.extension == ext || self.also.contains(&ext)
+    }
+}
+
+/// Containers that many formats are built on
+const ZIP_BASED: &[&str] = &[
+    "jar", "apk", "aab", "epub", "odt", "ods", "odp", "odg", "xpi", "crx", "cbz", "nupkg", "vsix", "whl",
+    "kmz", "3mf", "docx", "xlsx", "pptx", "docm", "xlsm", "pptm", "ipa", "appx", "msix",
+];
+const MP4_BASED: &[&str] = &["m4v", "m4a", "m4b", "mov", "3gp", "3g2", "heic", "heif", "avif", "f4v"];
+const PE_BASED: &[&str] = &["dll", "sys", "scr", "cpl", "ocx", "efi", "mui", "drv", "com"];
+
+/// Detects the real type of the file at `path` from its first bytes. Returns
+/// `None` for unreadable files and content it does not know.
+pub fn sniff(path: &Path) -> Option<Sniffed> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path).ok()?.take(HEADER_LEN as u64).read_to_end(&mut header).ok()?;
+    sniff_bytes(&header)
+}
+
+fn sniff_bytes(header: &[u8]) -> Option<Sniffed> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    let contains = |needle: &[u8]| header.windows(needle.len()).any(|w| w == needle);
+
+    let sniffed = if at(0, b"\x89PNG\r\n\x1a\n") {
+        Sniffed::new("png", &[])
+    } else if at(0, b"\xff\xd8\xff") {
+        Sniffed::new("jpg", &["jpeg", "jpe", "jfif"])
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Sniffed::new("gif", &[])
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        // Most camera RAW formats are TIFF inside
+        Sniffed::new("tiff", &["tif", "dng", "cr2", "nef", "nrw", "arw", "srf", "sr2", "orf", "pef", "srw", "rw2"])
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        Sniffed::new("webp", &[])
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Sniffed::new("wav", &[])
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        Sniffed::new("avi", &[])
+    } else if at(0, b"BM") && header.len() >= 26 && at(6, b"\0\0\0\0") {
+        Sniffed::new("bmp", &["dib"])
+    } else if at(0, b"\0\0\x01\0") {
+        Sniffed::new("ico", &["cur"])
+    } else if at(0, b"%PDF-") {
+        Sniffed::new("pdf", &["ai"])
+    } else if at(0, b"{\\rtf") {
+        Sniffed::new("rtf", &[])
+    } else if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") {
+        sniff_zip(header)
+    } else if at(0, b"Rar!\x1a\x07") {
+        Sniffed::new("rar", &["cbr"])
+    } else if at(0, b"7z\xbc\xaf\x27\x1c") {
+        Sniffed::new("7z", &[])
+    } else if at(0, b"\x1f\x8b") {
+        Sniffed::new("gz", &["tgz", "gzip"])
+    } else if at(0, b"BZh") {
+        Sniffed::new("bz2", &["tbz2", "tbz"])
+    } else if at(257, b"ustar") {
+        Sniffed::new("tar", &[])
+    } else if at(4, b"ftyp") {
+        sniff_mp4(header.get(8..12)?)
+    } else if at(0, b"\x1a\x45\xdf\xa3") {
+        if contains(b"webm") { Sniffed::new("webm", &["mkv"]) } else { Sniffed::new("mkv", &["mka", "mk3d", "webm"]) }
+    } else if at(0, b"fLaC") {
+        Sniffed::new("flac", &[])
+    } else if at(0, b"OggS") {
+        Sniffed::new("ogg", &["oga", "ogv", "opus", "spx"])
+    } else if at(0, b"ID3") || at(0, b"\xff\xfb") || at(0, b"\xff\xf3") || at(0, b"\xff\xf2") {
+        Sniffed::new("mp3", &[])
+    } else if at(0, b"\xff\xf1") || at(0, b"\xff\xf9") {
+        Sniffed::new("aac", &[])
+    } else if at(0, b"MZ") {
+        Sniffed::new("exe", PE_BASED)
+    } else if at(0, b"\x7fELF") {
+        Sniffed::new("elf", &["so", "o", "ko", "bin", "axf"])
+    } else {
+        return None;
+    };
+    Some(sniffed)
+}
+
+/// Office documents and OpenDocument files are ZIP archives with known entries
+fn sniff_zip(header: &[u8]) -> Sniffed {
+    let contains = |needle: &[u8]| header.windows(needle.len()).any(|w| w == needle);
+
+    if contains(b"word/") {
+        Sniffed::new("docx", &["docm", "dotx"])
+    } else if contains(b"xl/") {
+        Sniffed::new("xlsx", &["xlsm", "xltx"])
+    } else if contains(b"ppt/") {
+        Sniffed::new("pptx", &["pptm", "potx"])
+    } else if contains(b"application/vnd.oasis.opendocument.text") {
+        Sniffed::new("odt", &[])
+    } else if contains(b"application/epub+zip") {
+        Sniffed::new("epub", &[])
+    } else {
+        Sniffed::new("zip", ZIP_BASED)
+    }
+}
+
+/// ISO media files name their flavour in the brand of the `ftyp` box
+fn sniff_mp4(brand: &[u8]) -> Sniffed {
+    match brand {
+        b"M4A " | b"M4B " => Sniffed::new("m4a", &["m4b", "mp4"]),
+        b"qt  " => Sniffed::new("mov", &["mp4", "qt"]),
+        b"heic" | b"heix" | b"mif1" | b"msf1" => Sniffed::new("heic", &["heif", "hif"]),
+        b"avif" | b"avis" => Sniffed::new("avif", &[]),
+        b"crx " => Sniffed::new("cr3", &[]),
+        _ => Sniffed::new("mp4", MP4_BASED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extension(header: &[u8]) -> Option<&'static str> {
+        sniff_bytes(header).map(|sniffed| sniffed.extension)
+    }
+
+    /// The start of a ZIP archive whose first entry is `name`
+    fn zip(name: &str) -> Vec<u8> {
+        let mut header = b"PK\x03\x04".to_vec();
+        header.extend_from_slice(&[0; 26]);
+        header.extend_from_slice(name.as_bytes());
+        header
+    }
+
+    #[test]
+    fn images_and_documents() {
+        assert_eq!(extension(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("png"));
+        assert_eq!(extension(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("jpg"));
+        assert_eq!(extension(b"GIF89a"), Some("gif"));
+        assert_eq!(extension(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(extension(b"%PDF-1.7"), Some("pdf"));
+        assert!(sniff_bytes(b"\xff\xd8\xff\xe1").unwrap().accepts("jpeg"));
+        assert!(sniff_bytes(b"II*\0\x08\0\0\0").unwrap().accepts("nef"));
+    }
+
+    #[test]
+    fn zip_based_formats() {
+        assert_eq!(extension(&zip("word/document.xml")), Some("docx"));
+        assert_eq!(extension(&zip("xl/workbook.xml")), Some("xlsx"));
+        assert_eq!(extension(&zip("mimetypeapplication/epub+zip")), Some("epub"));
+        let plain = sniff_bytes(&zip("photos/a.jpg")).unwrap();
+        assert_eq!(plain.extension, "zip");
+        assert!(plain.accepts("jar") && !plain.accepts("pdf"));
+    }
+
+    #[test]
+    fn media_containers() {
+        assert_eq!(extension(b"\0\0\0\x20ftypM4A \0\0\0\0"), Some("m4a"));
+        assert_eq!(extension(b"\0\0\0\x18ftypheic"), Some("heic"));
+        assert_eq!(extension(b"\0\0\0\x18ftypisom"), Some("mp4"));
+        assert_eq!(extension(b"\0\0\0\x18ftypcrx "), Some("cr3"));
+        assert_eq!(extension(b"\x1a\x45\xdf\xa3\x01\0\0\0B\x82\x84webm"), Some("webm"));
+        assert_eq!(extension(b"ID3\x04\0"), Some("mp3"));
+        assert_eq!(extension(b"OggS\0\x02"), Some("ogg"));
+    }
+
+    #[test]
+    fn tar_and_executables() {
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(extension(&tar), Some("tar"));
+        assert!(sniff_bytes(b"MZ\x90\0").unwrap().accepts("dll"));
+        assert_eq!(extension(b"\x7fELF\x02\x01"), Some("elf"));
+    }
+
+    #[test]
+    fn unknown_or_short_content() {
+        assert_eq!(extension(b""), None);
+        assert_eq!(extension(b"P"), None);
+        assert_eq!(extension(b"\0\0\0\x18ftyp"), None);
+        assert_eq!(extension(b"hello, world"), None);
+    }
+}
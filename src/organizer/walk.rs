@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tokio::fs;
+
+use super::hidden::HiddenPolicy;
+use super::{Entry, History, ModeOptions, Organizer};
+
+/// Ignore file in gitignore syntax, read from the organized folder
+pub const IGNORE_FILE: &str = ".organizeignore";
+
+/// Files and folders left out by `--include`, `--exclude`, `.organizeignore`
+/// and `--hidden`
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Option<Globs>,
+    exclude: Option<Globs>,
+    ignore: Option<Gitignore>,
+    hidden: HiddenPolicy,
+}
+
+/// Globs without a slash match a file or folder name at any depth, the
+/// others the path relative to the organized folder
+#[derive(Debug, Clone)]
+struct Globs {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Globs {
+    fn parse(patterns: &str, option: &str) -> Result<Option<Self>> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        let mut any = false;
+
+        for pattern in patterns.lines().map(str::trim).filter(|p| !p.is_empty()) {
+            let normalized = pattern.replace('\\', "/");
+            let glob = GlobBuilder::new(normalized.trim_start_matches('/'))
+                .case_insensitive(true)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid {} pattern '{}'", option, pattern))?;
+            if normalized.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+            any = true;
+        }
+
+        if !any {
+            return Ok(None);
+        }
+        Ok(Some(Globs { names: names.build()?, paths: paths.build()? }))
+    }
+
+    fn is_match(&self, relative: &Path) -> bool {
+        relative.file_name().is_some_and(|name| self.names.is_match(name)) || self.paths.is_match(relative)
+    }
+}
+
+impl Filter {
+    /// The filters of `folder`: `--include` and `--exclude` (one glob per line),
+    /// the folder's `.organizeignore` and the `--hidden` policy
+    pub async fn load(folder: &Path, options: &ModeOptions) -> Result<Self> {
+        let hidden = HiddenPolicy::from_options(options)?;
+        let include = options.get::<String>("include").map(|p| Globs::parse(&p, "--include")).transpose()?.flatten();
+        let exclude = options.get::<String>("exclude").map(|p| Globs::parse(&p, "--exclude")).transpose()?.flatten();
+
+        let ignore_path = folder.join(IGNORE_FILE);
+        let ignore = if fs::try_exists(&ignore_path).await.unwrap_or(false) {
+            let mut builder = GitignoreBuilder::new(folder);
+            builder.case_insensitive(true)?;
+            if let Some(e) = builder.add(&ignore_path) {
+                return Err(e).with_context(|| format!("Invalid {}", ignore_path.display()));
+            }
+            Some(builder.build().with_context(|| format!("Invalid {}", ignore_path.display()))?)
+        } else {
+            None
+        };
+
+        Ok(Filter { include, exclude, ignore, hidden })
+    }
+
+    /// Whether the folder at `relative` is left out together with everything in it
+    pub fn skips_dir(&self, relative: &Path, metadata: &Metadata) -> bool {
+        self.hidden.skips(relative, metadata) || self.excludes(relative, true)
+    }
+
+    /// Whether the file at `relative` is left out
+    pub fn skips_file(&self, relative: &Path, metadata: &Metadata) -> bool {
+        self.hidden.skips(relative, metadata)
+            || self.excludes(relative, false)
+            || self.include.as_ref().is_some_and(|include| !include.is_match(relative))
+    }
+
+    fn excludes(&self, relative: &Path, is_dir: bool) -> bool {
+        self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(relative))
+            || self.ignore.as_ref().is_some_and(|ignore| ignore.matched(relative, is_dir).is_ignore())
+    }
+}
+
+/// Which part of a folder tree a mode looks at
+#[derive(Debug, Clone, Default)]
+pub struct Walk {
+    /// Levels of subfolders below the organized folder, `None` for all of them
+    max_depth: Option<usize>,
+    /// Organize every subfolder on its own instead of into the top-level layout
+    in_place: bool,
+    /// Folders an earlier organization created, by canonical path
+    skip_dirs: HashSet<PathBuf>,
+    filter: Filter,
+    /// Leave out linked files and folders instead of following them
+    skip_links: bool,
+}
+
+impl Walk {
+    /// Only the files directly in the folder
+    pub fn top_level() -> Self {
+        Walk { max_depth: Some(0), ..Default::default() }
+    }
+
+    /// The walk asked for with `--recursive`, `--max-depth` and `--collect`,
+    /// filtered by `--include`, `--exclude` and `.organizeignore`. Modes that
+    /// always work on the whole tree ignore the depth options.
+    pub async fn from_options<O: Organizer + ?Sized>(organizer: &O, folder: &Path, options: &ModeOptions) -> Result<Self> {
+        let filter = Filter::load(folder, options).await?;
+        let history = History::load(folder).await.unwrap_or_default();
+        let mut skip_dirs = quarantine_dirs(folder, options, &history).await;
+        if organizer.recursive() {
+            return Ok(Walk { skip_dirs, filter, ..Default::default() });
+        }
+
+        let max_depth = options.get::<String>("max-depth")
+            .map(|depth| depth.parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("Invalid --max-depth '{}', expected a number of folder levels", depth)))
+            .transpose()?;
+        let collect = options.get::<bool>("collect").unwrap_or(false);
+        if !options.get::<bool>("recursive").unwrap_or(false) && max_depth.is_none() {
+            if collect {
+                anyhow::bail!("--collect needs --recursive or --max-depth");
+            }
+            return Ok(Walk { filter, ..Walk::top_level() });
+        }
+
+        skip_dirs.extend(created_dirs(&history).await);
+        Ok(Walk { max_depth, in_place: !collect, skip_dirs, filter, ..Default::default() })
+    }
+
+    /// The same walk, leaving out links and junctions
+    pub fn without_links(self) -> Self {
+        Walk { skip_links: true, ..self }
+    }
+
+    /// Whether destinations are relative to each file's own folder
+    pub fn in_place(&self) -> bool {
+        self.in_place
+    }
+}
+
+/// Folders created by the organizations in the action log that still exist.
+/// Walking into them would sort already sorted files a second time.
+async fn created_dirs(history: &History) -> HashSet<PathBuf> {
+    let dirs: Vec<PathBuf> = history.actions.iter().flat_map(|action| action.plan.create_dirs.clone()).collect();
+    canonical_dirs(dirs).await
+}
+
+/// The `--quarantine` directory and the ones earlier duplicate removals used.
+/// Inside the folder they would otherwise be organized like any other files.
+async fn quarantine_dirs(folder: &Path, options: &ModeOptions, history: &History) -> HashSet<PathBuf> {
+    let mut dirs: Vec<PathBuf> = history.actions.iter().chain(&history.redo)
+        .filter_map(|action| action.plan.quarantine.clone())
+        .collect();
+    dirs.extend(options.get::<PathBuf>("quarantine").map(|dir| folder.join(dir)));
+    canonical_dirs(dirs).await
+}
+
+async fn canonical_dirs(dirs: Vec<PathBuf>) -> HashSet<PathBuf> {
+    let mut canonical_dirs = HashSet::new();
+    for dir in dirs {
+        if let Ok(canonical) = fs::canonicalize(dir).await {
+            canonical_dirs.insert(canonical);
+        }
+    }
+    canonical_dirs
+}
+
+/// The files and folders a walk found
+#[derive(Default)]
+pub struct Tree {
+    pub files: Vec<Entry>,
+    /// Folders below the walked one, parents before their children. Linked
+    /// folders are walked but not listed, so they are never removed.
+    pub dirs: Vec<PathBuf>,
+}
+
+/// Collects the files of `folder` that `walk` covers and its filter lets through
+pub async fn collect_entries(folder: &Path, walk: &Walk) -> Result<Vec<Entry>> {
+    Ok(walk_tree(folder, walk).await?.files)
+}
+
+/// Like `collect_entries`, also listing the folders that were walked
+pub async fn walk_tree(folder: &Path, walk: &Walk) -> Result<Tree> {
+    let mut tree = Tree::default();
+    let root = fs::canonicalize(folder).await?;
+    let mut visited = HashSet::from([root.clone()]);
+    collect_entries_in(folder, folder, 0, walk, &root, &mut visited, &mut tree).await?;
+    Ok(tree)
+}
+
+async fn collect_entries_in(
+    root: &Path,
+    current_dir: &Path,
+    depth: usize,
+    walk: &Walk,
+    canonical_root: &Path,
+    visited: &mut HashSet<PathBuf>,
+    tree: &mut Tree,
+) -> Result<()> {
+    let mut dir_entries = fs::read_dir(current_dir).await?;
+    loop {
+        let dir_entry = match dir_entries.next_entry().await {
+            Ok(Some(dir_entry)) => dir_entry,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Skipping the rest of {}: {}", current_dir.display(), e);
+                break;
+            }
+        };
+        let path = dir_entry.path();
+        let relative = path.strip_prefix(root).unwrap().to_path_buf();
+        let is_link = match dir_entry.file_type().await {
+            Ok(file_type) => file_type.is_symlink(),
+            Err(e) => {
+                eprintln!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if is_link && walk.skip_links {
+            continue;
+        }
+
+        // A dangling link has no target to look at, it is organized as a file
+        let metadata = match fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(_) => match dir_entry.metadata().await {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            },
+        };
+
+        if metadata.is_dir() {
+            if walk.max_depth.is_some_and(|max| depth >= max) || walk.filter.skips_dir(&relative, &metadata) {
+                continue;
+            }
+            // Links and junctions are followed once, and only within the folder
+            let canonical = match fs::canonicalize(&path).await {
+                Ok(canonical) => canonical,
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if !canonical.starts_with(canonical_root) || walk.skip_dirs.contains(&canonical) || !visited.insert(canonical) {
+                continue;
+            }
+            if !is_link {
+                tree.dirs.push(path.clone());
+            }
+            if let Err(e) = Box::pin(collect_entries_in(root, &path, depth + 1, walk, canonical_root, visited, tree)).await {
+                eprintln!("Skipping {}: {}", path.display(), e);
+            }
+        } else if (metadata.is_file() || metadata.is_symlink()) && !walk.filter.skips_file(&relative, &metadata) {
+            tree.files.push(Entry { path, relative, metadata });
+        }
+    }
+    Ok(())
+}
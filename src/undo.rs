@@ -1,84 +1,220 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use anyhow::Result;
 use tokio::fs;
 
-use crate::organizer::{OrganizeAction, get_log_path};
+use crate::organizer::link::{replace_with_copy, replace_with_link};
+use crate::organizer::remove_duplicates::calculate_hash;
+use crate::organizer::{History, Plan, move_file};
 
-pub async fn undo_last_action(folder: &Path) -> Result<()> {
-    let log_path = get_log_path(folder);
+/// Which part of the history `undo` rolls back
+#[derive(Debug, Clone, Copy)]
+pub enum UndoTarget {
+    /// The given number of most recent actions
+    Steps(usize),
+    /// Everything from the action with this id onwards
+    Id(u64),
+}
+
+/// Rolls back the actions `target` selects. A dry run only lists what would
+/// be reversed.
+pub async fn undo(folder: &Path, target: UndoTarget, dry_run: bool) -> Result<()> {
+    let mut history = History::load(folder).await?;
 
-    if !log_path.exists() {
+    if history.actions.is_empty() {
         println!("No organization history found for this folder.");
         return Ok(());
     }
 
-    let content = fs::read_to_string(&log_path).await?;
-    let mut actions: Vec<OrganizeAction> = serde_json::from_str(&content)?;
+    let steps = match target {
+        UndoTarget::Steps(steps) => steps.min(history.actions.len()),
+        UndoTarget::Id(id) => match history.actions.iter().position(|a| a.id == id) {
+            Some(index) => history.actions.len() - index,
+            None => anyhow::bail!("No action with id {} in the history, use --mode history to list them", id),
+        },
+    };
+
+    if dry_run {
+        for action in history.actions.iter().rev().take(steps) {
+            println!("Would undo #{} {} operation ({}):", action.id, action.plan.action_type, action.plan.summary());
+            print_undo(&action.plan);
+        }
+        return Ok(());
+    }
+
+    for _ in 0..steps {
+        let action = history.pop_undone().unwrap();
+        println!("Undoing #{} {} operation ({})...", action.id, action.plan.action_type, action.plan.summary());
+        undo_plan(folder, &action.plan).await;
+        // Save after every step so the log matches the disk if a later step fails
+        history.save(folder).await?;
+    }
+
+    Ok(())
+}
+
+/// Redoes the most recently undone action, or only lists it for a dry run
+pub async fn redo(folder: &Path, dry_run: bool) -> Result<()> {
+    let mut history = History::load(folder).await?;
 
-    if actions.is_empty() {
-        println!("No actions to undo.");
+    if dry_run {
+        match history.redo.last() {
+            Some(action) => println!("Would redo #{}, {}", action.id, action.plan),
+            None => println!("No undone actions to redo."),
+        }
         return Ok(());
     }
 
-    let last_action = actions.pop().unwrap();
-    println!("Undoing {} operation with {} file moves...", last_action.action_type, last_action.moves.len());
+    let Some(action) = history.pop_redone() else {
+        println!("No undone actions to redo.");
+        return Ok(());
+    };
+
+    println!("Redoing #{} {} operation ({})...", action.id, action.plan.action_type, action.plan.summary());
+    redo_plan(&action.plan).await;
+    history.save(folder).await
+}
+
+pub async fn list_history(folder: &Path) -> Result<()> {
+    let history = History::load(folder).await?;
+
+    if history.actions.is_empty() && history.redo.is_empty() {
+        println!("No organization history found for this folder.");
+        return Ok(());
+    }
+
+    println!("{:>4}  {:<19}  {:<20}  {:>6}", "ID", "Time", "Operation", "Moves");
+    let undone = history.redo.iter().rev().map(|a| (a, "  (undone)"));
+    for (action, note) in history.actions.iter().map(|a| (a, "")).chain(undone) {
+        println!("{:>4}  {:<19}  {:<20}  {:>6}{}",
+            action.id,
+            action.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            action.plan.action_type,
+            action.plan.moves.len(),
+            note);
+    }
+    Ok(())
+}
+
+/// The steps `undo_plan` would take, in the order it takes them
+fn print_undo(plan: &Plan) {
+    if !plan.deletes.is_empty() {
+        println!("  {} deleted files cannot be restored", plan.deletes.len());
+    }
+    for link in plan.links.iter().rev() {
+        println!("  copy    {} -> {} (was a {})", link.target.display(), link.path.display(), link.kind);
+    }
+    for file_move in plan.moves.iter().rev() {
+        println!("  move    {} -> {}", file_move.to.display(), file_move.from.display());
+    }
+    for dir in plan.create_dirs.iter().rev() {
+        println!("  rmdir   {} (if empty)", dir.display());
+    }
+}
+
+async fn undo_plan(folder: &Path, plan: &Plan) {
+    if !plan.deletes.is_empty() {
+        println!("{} deleted files cannot be restored", plan.deletes.len());
+    }
 
     let mut successful_undos = 0;
     let mut failed_undos = 0;
 
-    for file_move in last_action.moves.iter().rev() {
-        if last_action.action_type == "flatten" {
-            // Special handling for the flatten operation
-            let relative_path_str = file_move.to.file_name().unwrap().to_string_lossy().replace("___", "\\");
-            let original_path = folder.join(&relative_path_str);
-            let original_dir = original_path.parent().unwrap();
-
-            // Create directories if they don't exist
-            if !original_dir.exists() {
-                if let Err(e) = fs::create_dir_all(&original_dir).await {
-                    eprintln!("Failed to create directory {}: {}", original_dir.display(), e);
-                    failed_undos += 1;
-                    continue;
-                }
+    for link in plan.links.iter().rev() {
+        match replace_with_copy(link).await {
+            Ok(()) => successful_undos += 1,
+            Err(e) => {
+                eprintln!("Failed to turn {} back into a copy of {}: {}", link.path.display(), link.target.display(), e);
+                failed_undos += 1;
             }
+        }
+    }
 
-            match fs::rename(&file_move.to, &original_path).await {
-                Ok(()) => successful_undos += 1,
-                Err(e) => {
-                    eprintln!("Failed to move {} back to {}: {}", file_move.to.display(), original_path.display(), e);
-                    failed_undos += 1;
-                }
+    for file_move in plan.moves.iter().rev() {
+        if !file_move.to.exists() {
+            eprintln!("File not found for undo: {}", file_move.to.display());
+            failed_undos += 1;
+            continue;
+        }
+
+        if let Some(hash) = &file_move.hash {
+            if calculate_hash(&file_move.to).await.ok().as_ref() != Some(hash) {
+                eprintln!("Warning: {} changed since it was quarantined", file_move.to.display());
             }
-        } else {
-            // Existing undo logic for other operations
-            if file_move.to.exists() {
-                match fs::rename(&file_move.to, &file_move.from).await {
-                    Ok(()) => successful_undos += 1,
-                    Err(e) => {
-                        eprintln!("Failed to move {} back to {}: {}", file_move.to.display(), file_move.from.display(), e);
-                        failed_undos += 1;
-                    }
-                }
-            } else {
-                eprintln!("File not found for undo: {}", file_move.to.display());
+        }
+
+        // Directories removed by the operation (e.g. flatten) have to be recreated
+        if let Some(original_dir) = file_move.from.parent() {
+            if let Err(e) = fs::create_dir_all(original_dir).await {
+                eprintln!("Failed to create directory {}: {}", original_dir.display(), e);
+                failed_undos += 1;
+                continue;
+            }
+        }
+
+        match move_file(&file_move.to, &file_move.from).await {
+            Ok(()) => successful_undos += 1,
+            Err(e) => {
+                eprintln!("Failed to move {} back to {}: {}", file_move.to.display(), file_move.from.display(), e);
                 failed_undos += 1;
             }
         }
     }
 
-    if last_action.action_type != "flatten" {
+    if !plan.create_dirs.is_empty() {
+        for dir in plan.create_dirs.iter().rev() {
+            let _ = fs::remove_dir(dir).await;
+        }
+    } else if plan.action_type != "flatten" {
+        // Logs from older versions don't record the directories they created
         remove_empty_dirs(folder).await;
     }
 
-    if actions.is_empty() {
-        let _ = fs::remove_file(&log_path).await;
-    } else {
-        let content = serde_json::to_string_pretty(&actions)?;
-        fs::write(&log_path, content).await?;
+    println!("Undo completed: {} successful, {} failed", successful_undos, failed_undos);
+}
+
+async fn redo_plan(plan: &Plan) {
+    let mut successful_redos = 0;
+    let mut failed_redos = 0;
+
+    for file_move in &plan.moves {
+        if file_move.to.exists() {
+            eprintln!("Destination already exists, skipping: {}", file_move.to.display());
+            failed_redos += 1;
+            continue;
+        }
+
+        if let Some(dir) = file_move.to.parent() {
+            if let Err(e) = fs::create_dir_all(dir).await {
+                eprintln!("Failed to create directory {}: {}", dir.display(), e);
+                failed_redos += 1;
+                continue;
+            }
+        }
+
+        match move_file(&file_move.from, &file_move.to).await {
+            Ok(()) => successful_redos += 1,
+            Err(e) => {
+                eprintln!("Failed to move {} to {}: {}", file_move.from.display(), file_move.to.display(), e);
+                failed_redos += 1;
+            }
+        }
+    }
+
+    for link in &plan.links {
+        match replace_with_link(link).await {
+            Ok(()) => successful_redos += 1,
+            Err(e) => {
+                eprintln!("Failed to replace {} with a {}: {}", link.path.display(), link.kind, e);
+                failed_redos += 1;
+            }
+        }
     }
 
-    println!("Undo completed: {} successful, {} failed", successful_undos, failed_undos);
-    Ok(())
+    for dir in &plan.remove_dirs {
+        let _ = fs::remove_dir(dir).await;
+    }
+
+    println!("Redo completed: {} successful, {} failed", successful_redos, failed_redos);
 }
 
 async fn remove_empty_dirs(folder: &Path) {
@@ -94,4 +230,4 @@ async fn remove_empty_dirs(folder: &Path) {
             }
         }
     }
-}
\ No newline at end of file
+}